@@ -0,0 +1,230 @@
+/*
+Run a scenario headless and write the state of selected bodies to a trajectory file.
+
+batch_runner <scenario.ron|scenario.json> [options]
+    --ticks <n>         Maximum number of ticks to run
+    --for <time>        Run for a duration such as "3d 4h", or until a date in the scenario's calendar such as "2000-01-04 16:00"
+    --until <condition> Stop early once any recorded body meets the condition
+                        soi          the body moves into a parent or child system
+                        below:<d>    the body is closer than d to its system center
+                        above:<d>    the body is further than d from its system center
+    --body <name>       Record the dynamic body with this name. Can be repeated, defaults to all dynamic bodies
+    --every <n>         Record every n ticks (default 1). Energy and angular momentum drift is sampled at the same cadence
+    --output <path>     Trajectory file, format is picked from the extension (.csv or .jsonl). Defaults to stdout
+    --format <csv|jsonl>
+    --reference <path>  Also run a flat N-body reference simulation and write the position error of the tree to a csv file,
+                        sampled at the same cadence as records
+    --substeps <n>      Integration steps per tick for the reference simulation (default 1)
+*/
+
+use std::{io::{self, BufWriter, Write}, process::ExitCode, time::Instant};
+
+use gamelib::{diagnostics::EnergyDiagnostics, export::{BodyStateRecord, ExportFormat, RecordWriter}, gravity_system_tree::{body_id::BodyId, calendar::format_duration, reference::{PositionErrorSample, ReferenceEngine}, scenario::Scenario, system_manager::GravitySystemManager}};
+
+
+enum StopCondition {
+    SoiTransition,
+    Below(f64),
+    Above(f64),
+}
+impl StopCondition {
+    fn parse(s: &str) -> Result<Self, String> {
+        let parse_distance = |d: &str| d.parse::<f64>().map_err(|_| format!("invalid distance in condition: {s}"));
+        match s.split_once(':') {
+            None if s == "soi" => Ok(Self::SoiTransition),
+            Some(("below", d)) => Ok(Self::Below(parse_distance(d)?)),
+            Some(("above", d)) => Ok(Self::Above(parse_distance(d)?)),
+            _ => Err(format!("unknown condition: {s}")),
+        }
+    }
+
+    fn is_met(&self, manager: &GravitySystemManager, id: BodyId, start_transitions: u64) -> bool {
+        let Some(body) = manager.body_store.get_dynamic_body(id) else { return false };
+        match self {
+            Self::SoiTransition => body.get_soi_transitions() != start_transitions,
+            Self::Below(d) => body.relative_magnitude_squared() < d.powi(2),
+            Self::Above(d) => body.relative_magnitude_squared() > d.powi(2),
+        }
+    }
+}
+
+struct Options {
+    scenario: String,
+    ticks: Option<u64>,
+    time: Option<String>,
+    until: Option<StopCondition>,
+    bodies: Vec<String>,
+    every: u64,
+    output: Option<String>,
+    format: Option<ExportFormat>,
+    reference: Option<String>,
+    substeps: u32,
+}
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self { scenario: String::new(), ticks: None, time: None, until: None, bodies: vec![], every: 1, output: None, format: None, reference: None, substeps: 1 };
+        let mut scenario = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+            match arg.as_str() {
+                "--ticks" => options.ticks = Some(value()?.parse().map_err(|_| "invalid tick count".to_string())?),
+                "--for" => options.time = Some(value()?),
+                "--until" => options.until = Some(StopCondition::parse(&value()?)?),
+                "--body" => options.bodies.push(value()?),
+                "--every" => options.every = value()?.parse().ok().filter(|n| *n > 0).ok_or("invalid cadence".to_string())?,
+                "--output" => options.output = Some(value()?),
+                "--format" => options.format = Some(match value()?.as_str() {
+                    "csv" => ExportFormat::Csv,
+                    "jsonl" => ExportFormat::JsonLines,
+                    f => return Err(format!("unknown format: {f}")),
+                }),
+                "--reference" => options.reference = Some(value()?),
+                "--substeps" => options.substeps = value()?.parse().ok().filter(|n| *n > 0).ok_or("invalid substep count".to_string())?,
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {arg}")),
+                _ => scenario = Some(arg),
+            }
+        }
+        options.scenario = scenario.ok_or("no scenario file given".to_string())?;
+        if options.ticks.is_none() && options.time.is_none() && options.until.is_none() {
+            return Err("one of --ticks, --for or --until is required".into())
+        }
+        Ok(options)
+    }
+}
+
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE
+        }
+    };
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let scenario = Scenario::load(&options.scenario).map_err(|e| format!("failed to load scenario: {e:?}"))?;
+    let mut manager = scenario.build_manager().map_err(|e| format!("failed to build scenario: {e:?}"))?;
+
+    let store = &manager.body_store;
+    let recorded = if options.bodies.is_empty() {
+        store.dynamic_ids.clone()
+    } else {
+        options.bodies.iter().map(|name| {
+            store.dynamic_bodies
+                .iter()
+                .position(|b| b.get_name() == *name)
+                .map(|i| store.dynamic_ids[i])
+                .ok_or(format!("no dynamic body named {name}"))
+        }).collect::<Result<Vec<_>, _>>()?
+    };
+    let start_transitions = recorded.iter().map(|id| store.get_dynamic_body(*id).unwrap().get_soi_transitions()).collect::<Vec<_>>();
+    let total_transitions = |manager: &GravitySystemManager| manager.body_store.dynamic_bodies.iter().map(|b| b.get_soi_transitions()).sum::<u64>();
+    let initial_total_transitions = total_transitions(&manager);
+
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(BufWriter::new(std::fs::File::create(path).map_err(|e| format!("failed to create {path}: {e}"))?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let format = options.format.unwrap_or_else(|| options.output.as_ref().map_or(ExportFormat::Csv, ExportFormat::from_path));
+    let mut writer = RecordWriter::new(output, format);
+    let mut diagnostics = EnergyDiagnostics::default();
+    recorded.iter().for_each(|id| diagnostics.track(*id));
+    let mut reference = match &options.reference {
+        Some(path) => {
+            let mut output = BufWriter::new(std::fs::File::create(path).map_err(|e| format!("failed to create {path}: {e}"))?);
+            writeln!(output, "tick,max_error,mean_error,rms_error").map_err(|e| format!("failed to write reference error: {e}"))?;
+            Some((ReferenceEngine::from_manager(&manager).with_substeps(options.substeps), output, PositionErrorSample::default()))
+        }
+        None => None,
+    };
+    let sample_reference = |manager: &GravitySystemManager, reference: &mut Option<(ReferenceEngine, BufWriter<std::fs::File>, PositionErrorSample)>| -> Result<(), String> {
+        let Some((engine, output, worst)) = reference else { return Ok(()) };
+        let sample = PositionErrorSample::new(manager, engine);
+        writeln!(output, "{},{},{},{}", sample.time, sample.max, sample.mean, sample.rms).map_err(|e| format!("failed to write reference error: {e}"))?;
+        if sample.max >= worst.max {
+            *worst = sample;
+        }
+        Ok(())
+    };
+
+    let write_records = |manager: &GravitySystemManager, writer: &mut RecordWriter<Box<dyn Write>>| -> Result<(), String> {
+        for id in &recorded {
+            let Some(record) = BodyStateRecord::from_manager(manager, *id) else { continue };
+            writer.write(&record).map_err(|e| format!("failed to write record: {e}"))?;
+        }
+        Ok(())
+    };
+
+    let max_ticks = match &options.time {
+        Some(time) => {
+            let tick = manager.parse_tick(time).map_err(|e| format!("invalid time {time}: {e:?}"))?;
+            options.ticks.unwrap_or(u64::MAX).min(tick.max(0.).ceil() as u64)
+        }
+        None => options.ticks.unwrap_or(u64::MAX),
+    };
+    let start = Instant::now();
+    let mut ticks = 0;
+    let mut stop_reason = "tick limit reached";
+    let mut collisions = 0;
+    write_records(&manager, &mut writer)?;
+    diagnostics.sample(&manager);
+    sample_reference(&manager, &mut reference)?;
+    while ticks < max_ticks {
+        manager.step();
+        if let Some((engine, ..)) = &mut reference {
+            engine.step();
+        }
+        collisions += manager.take_collision_events().len();
+        ticks += 1;
+        let condition_met = options.until.as_ref().is_some_and(|c|
+            recorded.iter().zip(&start_transitions).any(|(id, t)| c.is_met(&manager, *id, *t))
+        );
+        if ticks % options.every == 0 || condition_met {
+            write_records(&manager, &mut writer)?;
+            diagnostics.sample(&manager);
+            sample_reference(&manager, &mut reference)?;
+        }
+        if condition_met {
+            stop_reason = "stop condition met";
+            break;
+        }
+    }
+    writer.flush().map_err(|e| format!("failed to write records: {e}"))?;
+    if let Some((_, output, _)) = &mut reference {
+        output.flush().map_err(|e| format!("failed to write reference error: {e}"))?;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    eprintln!("scenario:         {}", scenario.name);
+    eprintln!("stopped:          {stop_reason}");
+    eprintln!("ticks:            {ticks}");
+    eprintln!("simulated time:   {} ({}s per tick)", format_duration(manager.get_physical_time()), manager.get_tick_length());
+    eprintln!("date:             {}", manager.get_date(manager.get_current_time() as f64));
+    eprintln!("elapsed:          {elapsed:.3}s");
+    eprintln!("ticks per second: {:.1}", ticks as f64 / elapsed.max(f64::EPSILON));
+    eprintln!("soi transitions:  {}", total_transitions(&manager) - initial_total_transitions);
+    eprintln!("collisions:       {collisions}");
+    eprintln!("records written:  {}", writer.get_records_written());
+    for (id, tracker) in diagnostics.iter() {
+        let name = manager.body_store.get_dynamic_body(id).map_or(String::new(), |b| b.get_name());
+        eprintln!("{name} (body {}) since tick {} ({}):", id.0, tracker.reference_time, manager.get_date(tracker.reference_time as f64));
+        eprintln!("    energy drift:           current {:.3e}, max {:.3e}, mean {:.3e}", tracker.energy.current, tracker.energy.max, tracker.energy.mean);
+        eprintln!("    angular momentum drift: current {:.3e}, max {:.3e}, mean {:.3e}", tracker.angular_momentum.current, tracker.angular_momentum.max, tracker.angular_momentum.mean);
+    }
+    if let Some((engine, _, worst)) = &reference {
+        let last = PositionErrorSample::new(&manager, engine);
+        eprintln!("reference position error:");
+        eprintln!("    final: max {:.3e}, mean {:.3e}, rms {:.3e}", last.max, last.mean, last.rms);
+        eprintln!("    worst: max {:.3e} at tick {} ({})", worst.max, worst.time, manager.get_date(worst.time as f64));
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use gamelib::{bevy::{color::palettes::css::WHITE, math::DVec2}, gravity_system_tree::{builder::GravitySystemBuilder, dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}, system_manager::GravitySystemManager}, itertools::Itertools, math::*, G};
+use gamelib::{bevy::{color::palettes::css::WHITE, math::DVec2}, gravity_system_tree::{builder::GravitySystemBuilder, dynamic_body::DynamicBody, solver::GravitySolver, static_body::{StaticBody, StaticPosition}, system_manager::GravitySystemManager}, itertools::Itertools, math::*, G};
 use gamelib::bevy::prelude::Entity;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 
 
@@ -198,11 +199,110 @@ fn deep_tree_single_body(c: &mut Criterion) {
 }
 
 
+fn asteroid_belt(c: &mut Criterion) {
+    let star_mass = 1e22;
+    let star_mu = star_mass*G;
+    let mut rng = StdRng::seed_from_u64(0);
+    let asteroids = (0..10_000).map(|_| {
+        let radius = rng.gen_range(30_000. ..80_000.);
+        let direction = DVec2::from_angle(rng.gen_range(0. ..std::f64::consts::TAU));
+        DynamicBody::new(direction*radius, direction.perp()*(star_mu/radius).sqrt(), 1., 1., WHITE.into(), "".into())
+    }).collect_vec();
+    let test_system = GravitySystemBuilder::new()
+        .with_radius(1e9)
+        .with_position(StaticPosition::Still)
+        .with_time_step(1)
+        .with_static_bodies(&[
+            StaticBody::new(StaticPosition::Still, star_mass, 100., WHITE.into(), "".into()),
+            StaticBody::new(StaticPosition::Circular { radius: 100_000., speed: get_orbital_speed(star_mass, 100_000.), start_angle: 0. }, star_mass/1000., 10., WHITE.into(), "".into()),
+            StaticBody::new(StaticPosition::Circular { radius: 150_000., speed: get_orbital_speed(star_mass, 150_000.), start_angle: 2. }, star_mass/1000., 10., WHITE.into(), "".into()),
+            StaticBody::new(StaticPosition::Circular { radius: 200_000., speed: get_orbital_speed(star_mass, 200_000.), start_angle: 4. }, star_mass/1000., 10., WHITE.into(), "".into()),
+        ])
+        .with_dynamic_bodies(&asteroids);
+    let manager = GravitySystemManager::new(test_system);
+
+    c.bench_function("asteroid belt", |b| b.iter(|| {
+        let mut manager = manager.clone();
+        for _ in 0..100 {
+            manager.step();
+        }
+        black_box(manager);
+    }));
+}
+
+fn star_cluster(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let stars = (0..2_000).map(|_| {
+        let position = DVec2::from_angle(rng.gen_range(0. ..std::f64::consts::TAU)) * rng.gen_range(0. ..100_000.);
+        let velocity = DVec2::new(rng.gen_range(-1. ..1.), rng.gen_range(-1. ..1.));
+        DynamicBody::new(position, velocity, rng.gen_range(1e15..1e17), 1., WHITE.into(), "".into()).with_mutual_gravity(true)
+    }).collect_vec();
+    let cluster = |solver: GravitySolver| GravitySystemManager::new(GravitySystemBuilder::new()
+        .with_radius(1e9)
+        .with_position(StaticPosition::Still)
+        .with_time_step(1)
+        .with_solver(solver)
+        .with_dynamic_bodies(&stars));
+
+    let mut group = c.benchmark_group("star cluster");
+    group.sample_size(10);
+    for (name, solver) in [("direct", GravitySolver::Direct), ("barnes-hut", GravitySolver::BarnesHut { opening_angle: 0.5 })] {
+        let manager = cluster(solver);
+        group.bench_function(name, |b| b.iter(|| {
+            let mut manager = manager.clone();
+            for _ in 0..10 {
+                manager.step();
+            }
+            black_box(manager);
+        }));
+    }
+    group.finish();
+}
+
+fn many_child_systems(c: &mut Criterion) {
+    let galaxy_mass = 1e24;
+    let galaxy_mu = galaxy_mass*G;
+    let mut rng = StdRng::seed_from_u64(0);
+    let star_systems = (0..2_000).map(|_| {
+        let orbit_radius = rng.gen_range(1e5..1e7);
+        GravitySystemBuilder::new()
+            .with_radius(1_000.)
+            .with_position(StaticPosition::Circular { radius: orbit_radius, speed: get_orbital_speed(galaxy_mass, orbit_radius), start_angle: rng.gen_range(0. ..std::f64::consts::TAU) })
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, 1e15, 10., WHITE.into(), "".into())])
+    }).collect_vec();
+    let travellers = (0..2_000).map(|_| {
+        let radius = rng.gen_range(1e5..1e7);
+        let direction = DVec2::from_angle(rng.gen_range(0. ..std::f64::consts::TAU));
+        DynamicBody::new(direction*radius, direction.perp()*(galaxy_mu/radius).sqrt(), 1., 1., WHITE.into(), "".into())
+    }).collect_vec();
+    let galaxy = GravitySystemBuilder::new()
+        .with_radius(1e9)
+        .with_position(StaticPosition::Still)
+        .with_time_step(1)
+        .with_static_bodies(&[StaticBody::new(StaticPosition::Still, galaxy_mass, 100., WHITE.into(), "".into())])
+        .with_dynamic_bodies(&travellers)
+        .with_children(&star_systems);
+    let manager = GravitySystemManager::new(galaxy);
+
+    c.bench_function("many child systems", |b| b.iter(|| {
+        let mut manager = manager.clone();
+        for _ in 0..100 {
+            manager.step();
+        }
+        black_box(manager);
+    }));
+}
+
+
 
 
 criterion_group!(benches,
     single_layer_single_body_tree_benchmark,
     two_layer_populated_tree_benchmark,
-    deep_tree_single_body
+    deep_tree_single_body,
+    asteroid_belt,
+    star_cluster,
+    many_child_systems
 );
 criterion_main!(benches);
\ No newline at end of file
@@ -1,7 +1,7 @@
 use gamelib::{G, math::*, gravity_system_tree::{builder::*, dynamic_body::*, static_body::*, system_tree::*, system_manager::*}};
-use bevy::math::DVec2;
-use bevy::color::palettes::css::{CORNFLOWER_BLUE, GREEN, PURPLE, WHITE, YELLOW};
-use bevy::color::Color;
+use gamelib::bevy::math::DVec2;
+use gamelib::bevy::color::palettes::css::{CORNFLOWER_BLUE, GREEN, PURPLE, WHITE, YELLOW};
+use gamelib::bevy::color::Color;
 
 fn main() {
     let galaxy_mu = 1e33/100.;
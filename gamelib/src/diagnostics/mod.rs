@@ -0,0 +1,155 @@
+use bevy::{math::DVec2, prelude::Resource, utils::HashMap};
+
+use crate::gravity_system_tree::{body_id::BodyId, system_manager::GravitySystemManager, system_tree::DiscreteGravitySystemTime};
+
+
+
+/// Specific orbital energy and specific angular momentum of a body relative to the center of the system it is in. \
+/// For a body only affected by the system center both should stay constant, so any change is integration error.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OrbitalInvariants {
+    pub energy: f64,
+    pub angular_momentum: f64,
+}
+impl OrbitalInvariants {
+    pub fn new(mu: f64, relative_position: DVec2, relative_velocity: DVec2) -> Self {
+        Self {
+            energy: relative_velocity.length_squared() / 2. - mu / relative_position.length(),
+            angular_momentum: relative_position.perp_dot(relative_velocity),
+        }
+    }
+}
+
+
+/// Running statistics of the relative drift of a value from a reference value
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DriftStatistics {
+    pub current: f64,
+    /// Largest absolute relative drift seen
+    pub max: f64,
+    pub mean: f64,
+}
+impl DriftStatistics {
+    fn add_sample(&mut self, reference: f64, value: f64, samples: u64) {
+        self.current = if reference == 0. { value - reference } else { (value - reference) / reference.abs() };
+        self.max = self.max.max(self.current.abs());
+        self.mean += (self.current - self.mean) / samples as f64;
+    }
+}
+
+
+/// Drift of a body's orbital invariants since it entered its current system
+#[derive(Clone, Debug)]
+pub struct DriftTracker {
+    /// Invariants when tracking started or the body last changed system
+    pub reference: OrbitalInvariants,
+    pub current: OrbitalInvariants,
+    /// Tick that the reference was taken at
+    pub reference_time: DiscreteGravitySystemTime,
+    pub samples: u64,
+    pub energy: DriftStatistics,
+    pub angular_momentum: DriftStatistics,
+    /// Used to detect when the body changes system
+    soi_transitions: u64,
+}
+impl DriftTracker {
+    fn new(invariants: OrbitalInvariants, time: DiscreteGravitySystemTime, soi_transitions: u64) -> Self {
+        Self {
+            reference: invariants,
+            current: invariants,
+            reference_time: time,
+            samples: 0,
+            energy: DriftStatistics::default(),
+            angular_momentum: DriftStatistics::default(),
+            soi_transitions,
+        }
+    }
+
+    fn add_sample(&mut self, invariants: OrbitalInvariants) {
+        self.samples += 1;
+        self.current = invariants;
+        self.energy.add_sample(self.reference.energy, invariants.energy, self.samples);
+        self.angular_momentum.add_sample(self.reference.angular_momentum, invariants.angular_momentum, self.samples);
+    }
+}
+
+
+/// Tracks the energy and angular momentum drift of selected dynamic bodies. \
+/// Statistics start over whenever a body moves into a different system since the invariants are relative to the system's mu.
+#[derive(Resource, Default, Clone)]
+pub struct EnergyDiagnostics {
+    trackers: HashMap<BodyId, Option<DriftTracker>>,
+}
+impl EnergyDiagnostics {
+    pub fn track(&mut self, id: BodyId) {
+        self.trackers.entry(id).or_insert(None);
+    }
+    pub fn untrack(&mut self, id: BodyId) {
+        self.trackers.remove(&id);
+    }
+    pub fn is_tracked(&self, id: BodyId) -> bool {
+        self.trackers.contains_key(&id)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.trackers.is_empty()
+    }
+    /// Drift statistics of a tracked body, if it has been sampled yet
+    pub fn get(&self, id: BodyId) -> Option<&DriftTracker> {
+        self.trackers.get(&id)?.as_ref()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (BodyId, &DriftTracker)> {
+        self.trackers.iter().filter_map(|(id, t)| Some((*id, t.as_ref()?)))
+    }
+
+    /// Sample the current state of every tracked body in the manager
+    pub fn sample(&mut self, manager: &GravitySystemManager) {
+        for (id, tracker) in &mut self.trackers {
+            let Some(body) = manager.body_store.get_dynamic_body(*id) else { continue };
+            let Some(system) = manager.get_body_system(*id) else { continue };
+            let invariants = OrbitalInvariants::new(system.mu, body.get_current_relative_position(), body.get_current_relative_velocity());
+            match tracker {
+                Some(t) if t.soi_transitions == body.get_soi_transitions() => t.add_sample(invariants),
+                _ => *tracker = Some(DriftTracker::new(invariants, manager.get_current_time(), body.get_soi_transitions())),
+            }
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::Color;
+    use crate::{gravity_system_tree::{builder::GravitySystemBuilder, dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}}, G};
+    use super::*;
+
+    #[test]
+    fn circular_orbit_has_small_drift() {
+        let mass = 1e20;
+        let radius = 1000.;
+        let speed = (mass * G / radius).sqrt();
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e6)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, mass, 1., Color::WHITE, "".into())])
+            .with_dynamic_bodies(&[DynamicBody::new(DVec2::X * radius, DVec2::Y * speed, 1., 1., Color::WHITE, "".into())]);
+        let mut manager = GravitySystemManager::new(system);
+        let id = manager.body_store.dynamic_ids[0];
+
+        let mut diagnostics = EnergyDiagnostics::default();
+        diagnostics.track(id);
+        diagnostics.sample(&manager);
+        for _ in 0..10_000 {
+            manager.step();
+            diagnostics.sample(&manager);
+        }
+
+        let tracker = diagnostics.get(id).unwrap();
+        assert_eq!(tracker.samples, 10_000);
+        assert!(tracker.reference.energy < 0.);
+        assert!(tracker.energy.max < 1e-3, "{:?}", tracker.energy);
+        assert!(tracker.angular_momentum.max < 1e-3, "{:?}", tracker.angular_momentum);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use serde::Serialize;
+
+use crate::gravity_system_tree::{body_id::{BodyId, SystemId}, system_manager::GravitySystemManager, system_tree::DiscreteGravitySystemTime};
+
+
+
+/// Format of trajectory files. Both write one record per line so files can be streamed while the simulation runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+impl ExportFormat {
+    /// Pick the format from the file extension, defaulting to csv
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "json" | "ndjson") => Self::JsonLines,
+            _ => Self::Csv,
+        }
+    }
+}
+
+
+/// A row of a trajectory file
+pub trait ExportRecord: Serialize {
+    fn csv_header() -> &'static [&'static str];
+    fn csv_row(&self) -> Vec<String>;
+}
+
+/// Writes records to a file or any other writer in the chosen format. \
+/// The csv header is written before the first record.
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: ExportFormat,
+    wrote_header: bool,
+    records_written: u64,
+}
+impl RecordWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, format: ExportFormat) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, format: ExportFormat) -> Self {
+        Self { writer, format, wrote_header: false, records_written: 0 }
+    }
+
+    pub fn write<R: ExportRecord>(&mut self, record: &R) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Csv => {
+                if !self.wrote_header {
+                    writeln!(self.writer, "{}", R::csv_header().join(","))?;
+                    self.wrote_header = true;
+                }
+                let row = record.csv_row().iter().map(|f| escape_csv_field(f)).collect::<Vec<_>>();
+                writeln!(self.writer, "{}", row.join(","))?;
+            },
+            ExportFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, record)?;
+                writeln!(self.writer)?;
+            }
+        }
+        self.records_written += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_records_written(&self) -> u64 { self.records_written }
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+
+
+/// State of a dynamic body at a tick. \
+/// Absolute values are relative to the root system, relative values are relative to the center of the system that owns the body.
+#[derive(Serialize, Clone, Debug)]
+pub struct BodyStateRecord {
+    pub tick: DiscreteGravitySystemTime,
+    pub body: BodyId,
+    pub name: String,
+    pub system: Option<SystemId>,
+    pub x: f64,
+    pub y: f64,
+    pub vx: f64,
+    pub vy: f64,
+    pub relative_x: f64,
+    pub relative_y: f64,
+    pub relative_vx: f64,
+    pub relative_vy: f64,
+}
+impl BodyStateRecord {
+    /// Record the current state of a dynamic body in the manager
+    pub fn from_manager(manager: &GravitySystemManager, id: BodyId) -> Option<Self> {
+        let body = manager.body_store.get_dynamic_body(id)?;
+        let (position, velocity) = (body.get_current_absolute_position(), body.get_current_absolute_velocity());
+        let (relative_position, relative_velocity) = (body.get_current_relative_position(), body.get_current_relative_velocity());
+        Some(Self {
+            tick: manager.get_current_time(),
+            body: id,
+            name: body.get_name(),
+            system: manager.get_body_system_id(id),
+            x: position.x,
+            y: position.y,
+            vx: velocity.x,
+            vy: velocity.y,
+            relative_x: relative_position.x,
+            relative_y: relative_position.y,
+            relative_vx: relative_velocity.x,
+            relative_vy: relative_velocity.y,
+        })
+    }
+}
+impl ExportRecord for BodyStateRecord {
+    fn csv_header() -> &'static [&'static str] {
+        &["tick", "body", "name", "system", "x", "y", "vx", "vy", "relative_x", "relative_y", "relative_vx", "relative_vy"]
+    }
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            self.tick.to_string(),
+            self.body.0.to_string(),
+            self.name.clone(),
+            self.system.map_or(String::new(), |s| s.0.to_string()),
+            self.x.to_string(),
+            self.y.to_string(),
+            self.vx.to_string(),
+            self.vy.to_string(),
+            self.relative_x.to_string(),
+            self.relative_y.to_string(),
+            self.relative_vx.to_string(),
+            self.relative_vy.to_string(),
+        ]
+    }
+}
+
+
+/// Point along a predicted path. \
+/// Segments are split wherever the body changes system, relative values are relative to the center of the system the segment is in.
+#[derive(Serialize, Clone, Debug)]
+pub struct PredictedPathRecord {
+    pub body: BodyId,
+    pub segment: usize,
+    pub tick: DiscreteGravitySystemTime,
+    pub x: f64,
+    pub y: f64,
+    pub relative_x: f64,
+    pub relative_y: f64,
+}
+impl ExportRecord for PredictedPathRecord {
+    fn csv_header() -> &'static [&'static str] {
+        &["body", "segment", "tick", "x", "y", "relative_x", "relative_y"]
+    }
+    fn csv_row(&self) -> Vec<String> {
+        vec![
+            self.body.0.to_string(),
+            self.segment.to_string(),
+            self.tick.to_string(),
+            self.x.to_string(),
+            self.y.to_string(),
+            self.relative_x.to_string(),
+            self.relative_y.to_string(),
+        ]
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tick: DiscreteGravitySystemTime) -> PredictedPathRecord {
+        PredictedPathRecord { body: BodyId(3), segment: 0, tick, x: 1.5, y: -2., relative_x: 0.5, relative_y: 0. }
+    }
+
+    #[test]
+    fn csv_header_written_once() {
+        let mut writer = RecordWriter::new(vec![], ExportFormat::Csv);
+        writer.write(&record(1)).unwrap();
+        writer.write(&record(2)).unwrap();
+        let output = String::from_utf8(writer.writer).unwrap();
+        assert_eq!(output, "body,segment,tick,x,y,relative_x,relative_y\n3,0,1,1.5,-2,0.5,0\n3,0,2,1.5,-2,0.5,0\n");
+    }
+
+    #[test]
+    fn json_lines_one_record_per_line() {
+        let mut writer = RecordWriter::new(vec![], ExportFormat::JsonLines);
+        writer.write(&record(1)).unwrap();
+        writer.write(&record(2)).unwrap();
+        let output = String::from_utf8(writer.writer).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"body":3,"segment":0,"tick":1,"x":1.5,"y":-2.0,"relative_x":0.5,"relative_y":0.0}"#);
+    }
+
+    #[test]
+    fn csv_fields_are_escaped() {
+        assert_eq!(escape_csv_field("Satellite"), "Satellite");
+        assert_eq!(escape_csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// Handle to a dynamic or static body that is owned by the simulation. \
+/// Ids are handed out by the body store and never reused, so a handle does not depend on where the body is stored or on a bevy world.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BodyId(pub u64);
+
+/// Handle to a system in the gravity system tree. \
+/// Ids are assigned depth first when the tree is built, with the root system being 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SystemId(pub u64);
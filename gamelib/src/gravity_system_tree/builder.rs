@@ -2,7 +2,7 @@
 use std::{cell::RefCell, rc::Rc};
 use bevy::math::DVec2;
 
-use super::{dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{BodyStore, GravitySystemTree}, BodyPosition};
+use super::{body_id::SystemId, dynamic_body::DynamicBody, solver::GravitySolver, static_body::{StaticBody, StaticPosition}, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{BodyStore, GravitySystemTree}, BodyPosition};
 
 
 /// Only way to construct SystemTree objects
@@ -48,6 +48,11 @@ impl GravitySystemBuilder {
         self.system.time_step = time_step;
         self
     }
+    /// How dynamic bodies with mutual gravity in this system pull on each other. Defaults to direct summation
+    pub fn with_solver(mut self, solver: GravitySolver) -> Self {
+        self.system.solver = solver;
+        self
+    }
     pub fn with_children(mut self, builders: &[GravitySystemBuilder]) -> Self {
         self.child_systems.extend_from_slice(builders);
         self
@@ -56,10 +61,10 @@ impl GravitySystemBuilder {
     /// Fill in any SystemTree parameters that need to be calculated, then validate the tree to make sure everything makes sense
     /// Position needs to be calculated from the top down
     /// mass and child bodies needs to be calculated from the bottom up
-    /// Assign each static and dynamic body with a bevy entity used to associate it with a visual object
+    /// Assign each system a SystemId and each static and dynamic body a BodyId used to refer to it from outside the tree
     pub fn build(self) -> Result<(GravitySystemTree, BodyStore), SystemTreeError> {
         let mut body_store = BodyStore::default();
-        let mut tree = self.build_recursive(&mut body_store, 0, &StaticGenerator::new())?;
+        let mut tree = self.build_recursive(&mut body_store, &mut 0, 0, &StaticGenerator::new())?;
 
         body_store.update_static_bodies(&mut tree, 0.);
         //body_store.update_dynamic_bodies(&mut tree, 0);
@@ -67,14 +72,16 @@ impl GravitySystemBuilder {
         return Ok((tree, body_store));
     }
 
-    fn build_recursive(mut self, body_store: &mut BodyStore, system_depth: usize, parent_generator: &StaticGenerator) -> Result<GravitySystemTree, SystemTreeError> {
+    fn build_recursive(mut self, body_store: &mut BodyStore, next_system_id: &mut u64, system_depth: usize, parent_generator: &StaticGenerator) -> Result<GravitySystemTree, SystemTreeError> {
         if !self.set_position { return Err(SystemTreeError::NoPosition) }
         self.system.parent_generator = parent_generator.clone();
+        self.system.id = SystemId(*next_system_id);
+        *next_system_id += 1;
 
         let mut child_generator = parent_generator.clone();
         child_generator.push_end(self.system.position.clone());
         for child_system in self.child_systems {
-            let child_system = child_system.build_recursive(body_store, system_depth+1, &child_generator)?;
+            let child_system = child_system.build_recursive(body_store, next_system_id, system_depth+1, &child_generator)?;
             self.system.child_systems.push(child_system);
         }
 
@@ -87,13 +94,13 @@ impl GravitySystemBuilder {
 
 
         for mut body in self.dynamic_bodies {
-            body.initialize_in_system_tree(system_depth, &child_generator);
+            body.initialize_in_system_tree(system_depth, &child_generator, 0.);
             let index = body_store.add_dynamic_body_to_store(body);
             self.system.dynamic_body_indices.push(index);
         }
 
         for mut body in self.static_bodies {
-            body.initialize_in_system_tree(system_depth, &child_generator);
+            body.initialize_in_system_tree(system_depth, &child_generator, 0.);
             let index = body_store.add_static_body_to_store(body);
             self.system.static_body_indices.push(index);
         }
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+
+use super::system_tree::PhysicalTime;
+
+
+const MINUTE: f64 = 60.;
+const HOUR: f64 = 60. * MINUTE;
+const DAY: f64 = 24. * HOUR;
+/// Julian year, the year used by astronomers
+const YEAR: f64 = 365.25 * DAY;
+
+
+
+/// What time zero of a simulation corresponds to, used to show physical time as dates. \
+/// Dates ignore leap seconds, so every Gregorian day is exactly 86400 seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum Epoch {
+    /// No calendar, dates are shown as time elapsed since the start of the simulation
+    #[default]
+    Elapsed,
+    /// Gregorian calendar starting at the date
+    Gregorian(CalendarDate),
+    /// Calendar of years with a whole number of days, for worlds that don't follow the earth. \
+    /// Years are numbered from start_year and days from 1.
+    Custom { day_length: f64, days_per_year: u32, start_year: i64 },
+}
+impl Epoch {
+    /// Noon on January 1st 2000, the reference epoch used for most modern ephemerides
+    pub fn j2000() -> Self {
+        Self::Gregorian(CalendarDate { year: 2000, month: 1, day: 1, hour: 12, minute: 0, second: 0. })
+    }
+
+    /// Date of the physical time, which is seconds since the epoch
+    pub fn format_date(&self, time: PhysicalTime) -> String {
+        match self {
+            Self::Elapsed => format!("T+{}", format_duration(time)),
+            Self::Gregorian(start) => CalendarDate::from_unix_seconds(start.to_unix_seconds() + time).to_string(),
+            Self::Custom { day_length, days_per_year, start_year } => {
+                let days = (time / day_length).floor();
+                let years = (days / *days_per_year as f64).floor();
+                let day = days - years * *days_per_year as f64;
+                let seconds = (time - days * day_length).max(0.);
+                format!("Y{} D{} {}", start_year + years as i64, day as u64 + 1, format_clock(seconds))
+            }
+        }
+    }
+
+    /// Parse a point in time into seconds since the epoch. \
+    /// Accepts a date in the epoch's calendar, a duration after the epoch such as "3d 4h", or a duration after now when it starts with '+'.
+    pub fn parse_time(&self, input: &str, now: PhysicalTime) -> Result<PhysicalTime, TimeParseError> {
+        let input = input.trim();
+        if let Some(duration) = input.strip_prefix('+') {
+            return parse_duration(duration).map(|d| now + d)
+        }
+        match self {
+            Self::Elapsed => parse_duration(input.strip_prefix("T+").unwrap_or(input)),
+            Self::Gregorian(start) if looks_like_gregorian_date(input) => {
+                Ok(CalendarDate::parse(input)?.to_unix_seconds() - start.to_unix_seconds())
+            }
+            Self::Custom { day_length, days_per_year, start_year } if input.starts_with('Y') => {
+                let mut parts = input.splitn(3, ' ');
+                let year = parse_prefixed(parts.next(), 'Y')?;
+                let day = parse_prefixed(parts.next(), 'D')?;
+                if day < 1 || day > *days_per_year as i64 { return Err(TimeParseError::InvalidDate(input.to_string())) }
+                let into_day = parts.next().map_or(Ok(0.), parse_clock_or_duration)?;
+                let days = (year - start_year) as f64 * *days_per_year as f64 + (day - 1) as f64;
+                Ok(days * day_length + into_day)
+            }
+            _ => parse_duration(input),
+        }
+    }
+}
+
+
+/// Date and time of day in the proleptic Gregorian calendar
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CalendarDate {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: f64,
+}
+impl CalendarDate {
+    /// Seconds since 1970-01-01 00:00
+    pub fn to_unix_seconds(&self) -> f64 {
+        days_from_civil(self.year, self.month as i64, self.day as i64) as f64 * DAY
+            + self.hour as f64 * HOUR + self.minute as f64 * MINUTE + self.second
+    }
+
+    pub fn from_unix_seconds(seconds: f64) -> Self {
+        // Round to milliseconds first so times just before midnight don't show as 24:00:00
+        let milliseconds = (seconds * 1000.).round();
+        let whole_days = (milliseconds / (DAY * 1000.)).floor();
+        let seconds = (milliseconds - whole_days * DAY * 1000.) / 1000.;
+        let (year, month, day) = civil_from_days(whole_days as i64);
+        Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / HOUR) as u8,
+            minute: (seconds % HOUR / MINUTE) as u8,
+            second: seconds % MINUTE,
+        }
+    }
+
+    /// Parse "YYYY-MM-DD" with an optional time of day, "YYYY-MM-DD HH:MM" or "YYYY-MM-DDTHH:MM:SS"
+    pub fn parse(input: &str) -> Result<Self, TimeParseError> {
+        let invalid = || TimeParseError::InvalidDate(input.to_string());
+        let (date, time) = input.split_once([' ', 'T']).map_or((input, None), |(d, t)| (d, Some(t.trim())));
+        // Split from the right so negative years keep their sign
+        let mut fields = date.rsplitn(3, '-');
+        let day = fields.next().and_then(|d| d.parse::<u8>().ok()).ok_or_else(invalid)?;
+        let month = fields.next().and_then(|m| m.parse::<u8>().ok()).ok_or_else(invalid)?;
+        let year = fields.next().and_then(|y| y.parse::<i64>().ok()).ok_or_else(invalid)?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) { return Err(invalid()) }
+
+        let (mut hour, mut minute, mut second) = (0, 0, 0.);
+        if let Some(time) = time {
+            let mut fields = time.split(':');
+            hour = fields.next().and_then(|h| h.parse::<u8>().ok()).ok_or_else(invalid)?;
+            minute = fields.next().and_then(|m| m.parse::<u8>().ok()).ok_or_else(invalid)?;
+            second = fields.next().map_or(Some(0.), |s| s.parse::<f64>().ok()).ok_or_else(invalid)?;
+            if hour > 23 || minute > 59 || !(0. ..60.).contains(&second) || fields.next().is_some() { return Err(invalid()) }
+        }
+        Ok(Self { year, month, day, hour, minute, second })
+    }
+}
+impl std::fmt::Display for CalendarDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.hour as f64 * HOUR + self.minute as f64 * MINUTE + self.second;
+        write!(f, "{:04}-{:02}-{:02} {}", self.year, self.month, self.day, format_clock(seconds))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeParseError {
+    /// There was nothing to parse
+    Empty,
+    /// A number could not be read
+    InvalidNumber(String),
+    /// Durations can use y, d, h, m, s and ms
+    UnknownUnit(String),
+    /// The date does not exist or is not written in a known format
+    InvalidDate(String),
+}
+
+
+/// Format seconds as a duration such as "3d 4h 12m 5.5s", leaving out empty units
+pub fn format_duration(seconds: f64) -> String {
+    let milliseconds = (seconds.abs() * 1000.).round() as u64;
+    let sign = if seconds < 0. && milliseconds > 0 { "-" } else { "" };
+    let (days, rest) = (milliseconds / 86_400_000, milliseconds % 86_400_000);
+    let (hours, rest) = (rest / 3_600_000, rest % 3_600_000);
+    let (minutes, rest) = (rest / 60_000, rest % 60_000);
+
+    let mut parts = vec![];
+    if days > 0 { parts.push(format!("{days}d")) }
+    if hours > 0 { parts.push(format!("{hours}h")) }
+    if minutes > 0 { parts.push(format!("{minutes}m")) }
+    if rest > 0 || parts.is_empty() {
+        let seconds = format!("{}.{:03}", rest / 1000, rest % 1000);
+        parts.push(format!("{}s", seconds.trim_end_matches('0').trim_end_matches('.')));
+    }
+    format!("{sign}{}", parts.join(" "))
+}
+
+/// Parse a duration such as "3d 4h", "1.5h" or "90m" into seconds. \
+/// Units are y (Julian years), d, h, m, s and ms. A number without a unit is seconds.
+pub fn parse_duration(input: &str) -> Result<f64, TimeParseError> {
+    let input = input.trim();
+    if input.is_empty() { return Err(TimeParseError::Empty) }
+    let (sign, input) = input.strip_prefix('-').map_or((1., input), |rest| (-1., rest));
+
+    let mut total = 0.;
+    let mut chars = input.chars().filter(|c| !c.is_whitespace()).peekable();
+    while chars.peek().is_some() {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+            number.push(c);
+        }
+        let mut unit = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+            unit.push(c);
+        }
+        let value = number.parse::<f64>().map_err(|_| TimeParseError::InvalidNumber(number.clone()))?;
+        total += value * match unit.as_str() {
+            "y" => YEAR,
+            "d" => DAY,
+            "h" => HOUR,
+            "m" => MINUTE,
+            "s" | "" => 1.,
+            "ms" => 0.001,
+            _ => return Err(TimeParseError::UnknownUnit(unit)),
+        };
+    }
+    Ok(sign * total)
+}
+
+
+/// Time of day as HH:MM:SS, with milliseconds when there are any
+fn format_clock(seconds: f64) -> String {
+    let milliseconds = (seconds * 1000.).round() as u64;
+    let clock = format!("{:02}:{:02}:{:02}", milliseconds / 3_600_000, milliseconds / 60_000 % 60, milliseconds / 1000 % 60);
+    match milliseconds % 1000 {
+        0 => clock,
+        ms => format!("{clock}.{ms:03}"),
+    }
+}
+
+/// Time into a day, either as HH:MM[:SS] or as a duration
+fn parse_clock_or_duration(input: &str) -> Result<f64, TimeParseError> {
+    if !input.contains(':') { return parse_duration(input) }
+    let invalid = || TimeParseError::InvalidDate(input.to_string());
+    input.split(':').map(|f| f.parse::<f64>().map_err(|_| invalid())).zip([HOUR, MINUTE, 1.]).try_fold(0., |acc, (value, unit)| Ok(acc + value? * unit))
+}
+
+fn parse_prefixed(field: Option<&str>, prefix: char) -> Result<i64, TimeParseError> {
+    let field = field.ok_or(TimeParseError::Empty)?;
+    field.strip_prefix(prefix).and_then(|n| n.parse().ok()).ok_or_else(|| TimeParseError::InvalidDate(field.to_string()))
+}
+
+/// Dates have a dash after the year, durations never do
+fn looks_like_gregorian_date(input: &str) -> bool {
+    input.trim_start_matches('-').split_once('-').is_some_and(|(year, _)| !year.is_empty() && year.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a Gregorian date, from Howard Hinnant's date algorithms
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Gregorian year, month and day of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_round_trip() {
+        assert_eq!(parse_duration("3d 4h"), Ok(3. * DAY + 4. * HOUR));
+        assert_eq!(parse_duration("1.5h30m"), Ok(2. * HOUR));
+        assert_eq!(parse_duration("250ms"), Ok(0.25));
+        assert_eq!(parse_duration("-90"), Ok(-90.));
+        assert_eq!(parse_duration("3x"), Err(TimeParseError::UnknownUnit("x".into())));
+        assert_eq!(parse_duration("h"), Err(TimeParseError::InvalidNumber("".into())));
+        assert_eq!(parse_duration(" "), Err(TimeParseError::Empty));
+
+        assert_eq!(format_duration(3. * DAY + 4. * HOUR), "3d 4h");
+        assert_eq!(format_duration(61.25), "1m 1.25s");
+        assert_eq!(format_duration(0.), "0s");
+        assert_eq!(format_duration(-HOUR), "-1h");
+        for seconds in [0.001, 59.999, 86_399.5, 1e7 + 0.125] {
+            assert_eq!(parse_duration(&format_duration(seconds)), Ok(seconds));
+        }
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        let j2000 = Epoch::j2000();
+        assert_eq!(j2000.format_date(0.), "2000-01-01 12:00:00");
+        assert_eq!(j2000.format_date(-12. * HOUR - 0.5), "1999-12-31 23:59:59.500");
+        // 2000 is a leap year
+        assert_eq!(j2000.format_date(59. * DAY), "2000-02-29 12:00:00");
+        assert_eq!(j2000.format_date(366. * DAY), "2001-01-01 12:00:00");
+
+        assert_eq!(j2000.parse_time("2000-01-04 16:00", 0.), Ok(3. * DAY + 4. * HOUR));
+        assert_eq!(j2000.parse_time("3d 4h", 0.), Ok(3. * DAY + 4. * HOUR));
+        assert_eq!(j2000.parse_time("+1h", DAY), Ok(DAY + HOUR));
+        assert!(j2000.parse_time("2001-02-29", 0.).is_err());
+        for time in [-1e10, -1., 0., 1234.5, 1e9] {
+            assert_eq!(j2000.parse_time(&j2000.format_date(time), 0.), Ok(time));
+        }
+
+        let custom = Epoch::Custom { day_length: 10. * HOUR, days_per_year: 100, start_year: 5 };
+        assert_eq!(custom.format_date(0.), "Y5 D1 00:00:00");
+        assert_eq!(custom.format_date(101. * 10. * HOUR + 90.), "Y6 D2 00:01:30");
+        assert_eq!(custom.parse_time("Y6 D2 00:01:30", 0.), Ok(101. * 10. * HOUR + 90.));
+        assert_eq!(custom.parse_time("Y6 D2 1m30s", 0.), Ok(101. * 10. * HOUR + 90.));
+        assert!(custom.parse_time("Y6 D101", 0.).is_err());
+
+        assert_eq!(Epoch::Elapsed.format_date(3. * DAY + 4. * HOUR), "T+3d 4h");
+        assert_eq!(Epoch::Elapsed.parse_time("T+3d 4h", 0.), Ok(3. * DAY + 4. * HOUR));
+    }
+}
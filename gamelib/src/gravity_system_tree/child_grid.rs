@@ -0,0 +1,142 @@
+use bevy::math::DVec2;
+
+use super::{system_tree::{PhysicalTime, GravitySystemTree}, BodyPosition};
+
+
+
+/// Uniform grid over the child systems of a system, used to find the child system a body is entering without checking every child. \
+/// Child systems move, so the grid is rebuilt on every tick that bodies are checked. Building is O(children) and buffers are reused between ticks.
+#[derive(Clone, Debug, Default)]
+pub struct ChildSystemGrid {
+    /// Position of each child system at the time the grid was built
+    positions: Vec<BodyPosition>,
+    /// Squared radius of each child system
+    radii_squared: Vec<f64>,
+    /// Corner of the grid with the smallest coordinates
+    min: DVec2,
+    cell_size: f64,
+    columns: usize,
+    rows: usize,
+    /// Index into entries where each cell starts, with an extra element at the end
+    cell_starts: Vec<usize>,
+    /// Child system indices grouped by cell. Within a cell, indices are in ascending order.
+    entries: Vec<usize>,
+}
+impl ChildSystemGrid {
+    /// Place the child systems at their position at the provided time. \
+    /// Each child is added to every cell its bounding box touches.
+    pub fn rebuild(&mut self, child_systems: &[GravitySystemTree], time: PhysicalTime) {
+        self.positions.clear();
+        self.radii_squared.clear();
+        self.cell_starts.clear();
+        self.entries.clear();
+        if child_systems.is_empty() { return }
+
+        let mut min = DVec2::INFINITY;
+        let mut max = DVec2::NEG_INFINITY;
+        for child_system in child_systems {
+            let position = child_system.position.get_position(time);
+            min = min.min(position - child_system.radius);
+            max = max.max(position + child_system.radius);
+            self.positions.push(position);
+            self.radii_squared.push(child_system.radius.powi(2));
+        }
+
+        // Aim for roughly one cell per child system
+        let side = (child_systems.len() as f64).sqrt().ceil();
+        let extent = (max - min).max_element();
+        self.min = min;
+        self.cell_size = if extent > 0. { extent / side } else { 1. };
+        self.columns = (((max.x - min.x) / self.cell_size) as usize + 1).min(side as usize + 1);
+        self.rows = (((max.y - min.y) / self.cell_size) as usize + 1).min(side as usize + 1);
+
+        // Count entries per cell, turn the counts into start offsets, then fill the cells in child order
+        self.cell_starts.resize(self.columns * self.rows + 1, 0);
+        for (position, child_system) in self.positions.iter().zip(child_systems) {
+            let (x_range, y_range) = self.cell_range(*position, child_system.radius);
+            for y in y_range {
+                for x in x_range.clone() {
+                    self.cell_starts[y * self.columns + x + 1] += 1;
+                }
+            }
+        }
+        for i in 1..self.cell_starts.len() {
+            self.cell_starts[i] += self.cell_starts[i-1];
+        }
+        self.entries.resize(self.cell_starts[self.cell_starts.len()-1], 0);
+        let mut cursors = self.cell_starts.clone();
+        for (index, (position, child_system)) in self.positions.iter().zip(child_systems).enumerate() {
+            let (x_range, y_range) = self.cell_range(*position, child_system.radius);
+            for y in y_range {
+                for x in x_range.clone() {
+                    let cursor = &mut cursors[y * self.columns + x];
+                    self.entries[*cursor] = index;
+                    *cursor += 1;
+                }
+            }
+        }
+    }
+
+    /// Find the first child system, in child order, that contains the position. \
+    /// Gives the same result as checking every child system in order.
+    pub fn find_containing(&self, position: BodyPosition) -> Option<usize> {
+        if self.entries.is_empty() { return None }
+        let cell = self.cell(position)?;
+        self.entries[self.cell_starts[cell]..self.cell_starts[cell+1]]
+            .iter()
+            .cloned()
+            .find(|index| position.distance_squared(self.positions[*index]) <= self.radii_squared[*index])
+    }
+
+    fn cell(&self, position: BodyPosition) -> Option<usize> {
+        let offset = (position - self.min) / self.cell_size;
+        if offset.x < 0. || offset.y < 0. || !offset.is_finite() { return None }
+        let (x, y) = (offset.x as usize, offset.y as usize);
+        if x > self.columns || y > self.rows { return None }
+        Some(y.min(self.rows-1) * self.columns + x.min(self.columns-1))
+    }
+
+    fn cell_range(&self, position: BodyPosition, radius: f64) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let low = ((position - radius - self.min) / self.cell_size).max(DVec2::ZERO);
+        let high = (position + radius - self.min) / self.cell_size;
+        let x_range = (low.x as usize).min(self.columns-1)..(high.x as usize).min(self.columns-1)+1;
+        let y_range = (low.y as usize).min(self.rows-1)..(high.y as usize).min(self.rows-1)+1;
+        (x_range, y_range)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::gravity_system_tree::static_body::StaticPosition;
+    use super::*;
+
+    #[test]
+    fn grid_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let child_systems = (0..500).map(|_| GravitySystemTree {
+            position: StaticPosition::Circular { radius: rng.gen_range(1e3..1e6), speed: rng.gen_range(0. ..0.01), start_angle: rng.gen_range(0. ..6.3) },
+            radius: if rng.gen_bool(0.05) { rng.gen_range(1e4..2e5) } else { rng.gen_range(10. ..5e3) },
+            ..Default::default()
+        }).collect::<Vec<_>>();
+
+        let mut grid = ChildSystemGrid::default();
+        for time in [0., 1e3, 5e4] {
+            grid.rebuild(&child_systems, time);
+            let random_positions = (0..20_000).map(|_| DVec2::new(rng.gen_range(-1.2e6..1.2e6), rng.gen_range(-1.2e6..1.2e6)));
+            // Positions right on the edge of each system
+            let edge_positions = child_systems.iter().map(|s| s.position.get_position(time) + DVec2::from_angle(1.) * s.radius);
+            for position in random_positions.chain(edge_positions) {
+                let expected = child_systems
+                    .iter()
+                    .position(|s| position.distance_squared(s.position.get_position(time)) <= s.radius.powi(2));
+                assert_eq!(grid.find_containing(position), expected);
+            }
+        }
+        grid.rebuild(&[], 0.);
+        assert_eq!(grid.find_containing(DVec2::ZERO), None);
+    }
+}
@@ -0,0 +1,290 @@
+use bevy::math::DVec2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{body_id::{BodyId, SystemId}, dynamic_body::{DynamicBodies, DynamicBody, DynamicBodyRef}, system_tree::{DiscreteGravitySystemTime, GravitySystemTree}, BodyRadius};
+
+
+
+/// What happens when two dynamic bodies in the same system touch while moving towards each other
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CollisionOutcome {
+    /// The bodies always merge into one
+    Merge,
+    /// Impacts slower than speed merge the bodies. \
+    /// Faster impacts break both bodies into fragments that fly away from their center of mass at dispersion times the impact speed.
+    Fragment { speed: f64, fragments: usize, dispersion: f64 },
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CollisionSettings {
+    pub outcome: CollisionOutcome,
+    /// Seed for the randomness of fragment velocities, so runs can be reproduced
+    #[serde(default)]
+    pub seed: u64,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionKind {
+    Merge,
+    Fragment,
+}
+
+/// Record of a collision that was resolved, used to keep anything outside the simulation in sync with the bodies that were removed and created
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollisionEvent {
+    pub time: DiscreteGravitySystemTime,
+    pub kind: CollisionKind,
+    /// Relative speed of the bodies when they touched
+    pub impact_speed: f64,
+    pub removed: [BodyId; 2],
+    pub created: Vec<BodyId>,
+}
+
+
+/// Collision settings and state of a system manager
+#[derive(Clone, Debug)]
+pub(super) struct CollisionHandler {
+    pub settings: CollisionSettings,
+    rng: StdRng,
+    /// Collisions resolved since events were last taken
+    pub events: Vec<CollisionEvent>,
+}
+impl CollisionHandler {
+    pub fn new(settings: CollisionSettings) -> Self {
+        Self { rng: StdRng::seed_from_u64(settings.seed), settings, events: vec![] }
+    }
+
+    /// Bodies that replace the two colliding bodies, along with how the collision was resolved
+    pub fn resolve(&mut self, a: DynamicBodyRef, b: DynamicBodyRef) -> (CollisionKind, f64, Vec<DynamicBody>) {
+        let impact_speed = a.get_current_relative_velocity().distance(b.get_current_relative_velocity());
+        match self.settings.outcome {
+            CollisionOutcome::Fragment { speed, fragments, dispersion } if impact_speed >= speed && fragments > 1 => {
+                (CollisionKind::Fragment, impact_speed, fragment(a, b, fragments, dispersion * impact_speed, &mut self.rng))
+            }
+            _ => (CollisionKind::Merge, impact_speed, vec![merge(a, b)]),
+        }
+    }
+}
+
+
+/// Find pairs of dynamic bodies in the same system that overlap and are moving towards each other. \
+/// Each body is in at most one pair. Bodies in different systems never collide, so each pair comes with the system that holds both bodies.
+pub(super) fn find_collisions(system: &GravitySystemTree, bodies: &DynamicBodies) -> Vec<(SystemId, usize, usize)> {
+    let mut pairs = vec![];
+    find_collisions_recursive(system, bodies, &mut pairs, &mut vec![]);
+    pairs
+}
+fn find_collisions_recursive(system: &GravitySystemTree, bodies: &DynamicBodies, pairs: &mut Vec<(SystemId, usize, usize)>, sweep: &mut Vec<(f64, usize)>) {
+    if system.total_child_dynamic_bodies < 2 { return }
+
+    // Sweep along x so only bodies whose x extents overlap are compared
+    sweep.clear();
+    sweep.extend(system.dynamic_body_indices.iter().map(|i| {
+        let body = bodies.at(*i);
+        (body.get_current_relative_position().x - body.get_radius(), *i)
+    }));
+    sweep.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut used = vec![false; sweep.len()];
+    for i in 0..sweep.len() {
+        if used[i] { continue }
+        let a = bodies.at(sweep[i].1);
+        let max_x = a.get_current_relative_position().x + a.get_radius();
+        for j in i+1..sweep.len() {
+            if sweep[j].0 > max_x { break }
+            if used[j] { continue }
+            let b = bodies.at(sweep[j].1);
+            if is_colliding(a, b) {
+                pairs.push((system.id, sweep[i].1, sweep[j].1));
+                used[i] = true;
+                used[j] = true;
+                break
+            }
+        }
+    }
+
+    for child_system in &system.child_systems {
+        find_collisions_recursive(child_system, bodies, pairs, sweep);
+    }
+}
+
+fn is_colliding(a: DynamicBodyRef, b: DynamicBodyRef) -> bool {
+    let offset = b.get_current_relative_position() - a.get_current_relative_position();
+    let closing = offset.dot(b.get_current_relative_velocity() - a.get_current_relative_velocity()) < 0.;
+    closing && offset.length_squared() < (a.get_radius() + b.get_radius()).powi(2)
+}
+
+
+/// Density of a sphere with the mass and radius
+fn get_density(mass: f64, radius: BodyRadius) -> f64 {
+    mass / (4. / 3. * std::f64::consts::PI * radius.powi(3))
+}
+
+/// Radius of a sphere with the mass and density
+fn get_radius(mass: f64, density: f64) -> BodyRadius {
+    (mass / (4. / 3. * std::f64::consts::PI * density)).cbrt()
+}
+
+/// Radius of a body with the mass of both bodies and the mass weighted average of their densities. \
+/// Massless bodies have no density and add no weight, so two massless bodies keep the volume of both instead.
+fn combined_radius(a: DynamicBodyRef, b: DynamicBodyRef) -> BodyRadius {
+    let mass = a.get_mass() + b.get_mass();
+    if mass <= 0. { return (a.get_radius().powi(3) + b.get_radius().powi(3)).cbrt() }
+    let weighted_density = |body: DynamicBodyRef| if body.get_mass() > 0. { get_density(body.get_mass(), body.get_radius()) * body.get_mass() } else { 0. };
+    get_radius(mass, (weighted_density(a) + weighted_density(b)) / mass)
+}
+
+/// Center of mass position, center of mass velocity and total mass of the two bodies. \
+/// Two massless bodies have no center of mass, so the average of their positions and velocities is used.
+fn center_of_mass(a: DynamicBodyRef, b: DynamicBodyRef) -> (DVec2, DVec2, f64) {
+    let mass = a.get_mass() + b.get_mass();
+    if mass <= 0. {
+        let position = (a.get_current_relative_position() + b.get_current_relative_position()) / 2.;
+        let velocity = (a.get_current_relative_velocity() + b.get_current_relative_velocity()) / 2.;
+        return (position, velocity, mass)
+    }
+    let position = (a.get_current_relative_position() * a.get_mass() + b.get_current_relative_position() * b.get_mass()) / mass;
+    let velocity = (a.get_current_relative_velocity() * a.get_mass() + b.get_current_relative_velocity() * b.get_mass()) / mass;
+    (position, velocity, mass)
+}
+
+/// One body with the mass and momentum of both. It takes the name and color of the heavier body.
+fn merge(a: DynamicBodyRef, b: DynamicBodyRef) -> DynamicBody {
+    let (position, velocity, mass) = center_of_mass(a, b);
+    let heavier = if a.get_mass() >= b.get_mass() { a } else { b };
+    DynamicBody::new(position, velocity, mass, combined_radius(a, b), heavier.get_color(), heavier.get_name())
+        .with_mutual_gravity(a.get_mutual_gravity() || b.get_mutual_gravity())
+}
+
+/// Equal fragments with the density of the combined body, spread around its edge and flying outwards at up to speed. \
+/// The average fragment velocity is the center of mass velocity, so mass and momentum are conserved.
+fn fragment(a: DynamicBodyRef, b: DynamicBodyRef, fragments: usize, speed: f64, rng: &mut StdRng) -> Vec<DynamicBody> {
+    let (position, velocity, mass) = center_of_mass(a, b);
+    let radius = combined_radius(a, b);
+    let heavier = if a.get_mass() >= b.get_mass() { a } else { b };
+    let mutual_gravity = a.get_mutual_gravity() || b.get_mutual_gravity();
+
+    // Fragments get evenly spaced directions with some jitter so they don't overlap and always move apart
+    let slice = std::f64::consts::TAU / fragments as f64;
+    let offset = rng.gen_range(0. ..slice);
+    let directions = (0..fragments).map(|i| DVec2::from_angle(offset + slice * (i as f64 + rng.gen_range(-0.25..0.25)))).collect::<Vec<_>>();
+    let dispersions = directions.iter().map(|d| *d * speed * rng.gen_range(0.5..1.)).collect::<Vec<_>>();
+    let mean_dispersion = dispersions.iter().sum::<DVec2>() / fragments as f64;
+
+    directions
+        .iter()
+        .zip(&dispersions)
+        .enumerate()
+        .map(|(i, (direction, dispersion))| DynamicBody::new(
+            position + *direction * radius,
+            velocity + *dispersion - mean_dispersion,
+            mass / fragments as f64,
+            radius / (fragments as f64).cbrt(),
+            heavier.get_color(),
+            format!("{} fragment {}", heavier.get_name(), i + 1),
+        ).with_mutual_gravity(mutual_gravity))
+        .collect()
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::Color;
+    use crate::gravity_system_tree::{builder::GravitySystemBuilder, static_body::StaticPosition, system_manager::GravitySystemManager};
+    use super::*;
+
+    fn head_on(speed: f64, settings: CollisionSettings) -> GravitySystemManager {
+        let bodies = [
+            DynamicBody::new(DVec2::new(-20., 1.), DVec2::X * speed, 3., 4., Color::WHITE, "big".into()),
+            DynamicBody::new(DVec2::new(20., 0.), DVec2::NEG_X * speed, 1., 3., Color::BLACK, "small".into()),
+            DynamicBody::new(DVec2::new(0., 500.), DVec2::ZERO, 1., 1., Color::WHITE, "bystander".into()),
+        ];
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e6)
+            .with_time_step(1)
+            .with_dynamic_bodies(&bodies);
+        let mut manager = GravitySystemManager::new(system).with_collision_settings(Some(settings));
+        for _ in 0..2000 {
+            manager.step();
+        }
+        manager
+    }
+
+    fn momentum(manager: &GravitySystemManager) -> DVec2 {
+        manager.body_store.dynamic_bodies.iter().map(|b| b.get_current_relative_velocity() * b.get_mass()).sum()
+    }
+
+    #[test]
+    fn slow_impact_merges_bodies() {
+        let settings = CollisionSettings { outcome: CollisionOutcome::Fragment { speed: 1000., fragments: 5, dispersion: 0.5 }, seed: 0 };
+        let mut manager = head_on(100., settings);
+        let events = manager.take_collision_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, CollisionKind::Merge);
+        assert_eq!(events[0].created.len(), 1);
+
+        let bodies = &manager.body_store.dynamic_bodies;
+        assert_eq!(bodies.len(), 2);
+        let merged = manager.body_store.get_dynamic_body(events[0].created[0]).unwrap();
+        assert_eq!(merged.get_mass(), 4.);
+        assert_eq!(merged.get_name(), "big");
+        // Density is the mass weighted average of 3 / 64 and 1 / 27, in units of 4/3 pi
+        let density: f64 = (3. * 3. / 64. + 1. / 27.) / 4.;
+        assert!((merged.get_radius() - (4. / density).cbrt()).abs() < 1e-12);
+        assert!((momentum(&manager) - DVec2::X * 200.).length() < 1e-9);
+        assert!(manager.take_collision_events().is_empty());
+    }
+
+    #[test]
+    fn massless_bodies_merge_at_their_midpoint() {
+        let bodies = [
+            DynamicBody::new(DVec2::new(-2., 0.), DVec2::X * 100., 0., 4., Color::WHITE, "left".into()),
+            DynamicBody::new(DVec2::new(2., 1.), DVec2::NEG_X * 100., 0., 3., Color::BLACK, "right".into()),
+        ];
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e6)
+            .with_time_step(1)
+            .with_dynamic_bodies(&bodies);
+        let settings = CollisionSettings { outcome: CollisionOutcome::Merge, seed: 0 };
+        let mut manager = GravitySystemManager::new(system).with_collision_settings(Some(settings));
+        manager.step();
+        let events = manager.take_collision_events();
+        assert_eq!(events.len(), 1);
+
+        let merged = manager.body_store.get_dynamic_body(events[0].created[0]).unwrap();
+        assert_eq!(merged.get_mass(), 0.);
+        assert!(merged.get_current_relative_position().distance(DVec2::new(0., 0.5)) < 1e-9);
+        assert_eq!(merged.get_current_relative_velocity(), DVec2::ZERO);
+        assert!((merged.get_radius() - 91_f64.cbrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fast_impact_fragments_bodies() {
+        let settings = CollisionSettings { outcome: CollisionOutcome::Fragment { speed: 1000., fragments: 6, dispersion: 0.5 }, seed: 7 };
+        let mut manager = head_on(2000., settings.clone());
+        let events = manager.take_collision_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, CollisionKind::Fragment);
+        assert_eq!(events[0].created.len(), 6);
+
+        // Fragments fly apart instead of colliding with each other, and keep the mass and momentum of the impact
+        let store = &manager.body_store;
+        assert_eq!(store.dynamic_bodies.len(), 7);
+        let fragments = events[0].created.iter().map(|id| store.get_dynamic_body(*id).unwrap()).collect::<Vec<_>>();
+        assert!((fragments.iter().map(|f| f.get_mass()).sum::<f64>() - 4.).abs() < 1e-12);
+        assert!((momentum(&manager) - DVec2::X * 4000.).length() < 1e-6);
+
+        // The same seed gives the same fragments
+        let mut again = head_on(2000., settings);
+        assert_eq!(again.take_collision_events(), events);
+        for id in &events[0].created {
+            assert_eq!(again.body_store.get_dynamic_body(*id).unwrap().get_current_relative_position(), store.get_dynamic_body(*id).unwrap().get_current_relative_position());
+        }
+    }
+}
@@ -1,192 +1,450 @@
+use std::marker::PhantomData;
+
 use bevy::{color::Color, math::DVec2};
 use crate::G;
-use super::{future_actions::FutureActions, static_body::StaticPosition, static_generator::StaticGenerator, system_tree::{DiscreteGravitySystemTime, GravitySystemTime}, BodyAcceleration, BodyMass, BodyPosition, BodyRadius, BodyVelocity, GravitationalParameter, CALCULATION_TIME_STEP};
+use super::{future_actions::FutureActions, solver::BarnesHutTree, static_body::StaticPosition, static_generator::StaticGenerator, system_tree::{PhysicalTime, TickContext}, BodyAcceleration, BodyMass, BodyPosition, BodyRadius, BodyVelocity, GravitationalParameter};
+
+
+/// Number of bodies the gravity kernel works on at once
+const GRAVITY_LANES: usize = 4;
+
 
 
-/// A body that does not effect gravity but is effected by gravity
+/// A body that does not effect gravity but is effected by gravity. \
+/// This only describes a body until it is added to a body store, where its state is split into the columns of DynamicBodies.
 #[derive(Clone, Debug)]
 pub struct DynamicBody {
-    previous_relative_position: BodyPosition,
-    current_relative_position: BodyPosition,
-    previous_absolute_position: BodyPosition,
-    current_absolute_position: BodyPosition,
+    relative_position: BodyPosition,
+    absolute_position: BodyPosition,
+    relative_velocity: BodyVelocity,
+    absolute_velocity: BodyVelocity,
+    info: DynamicBodyInfo,
+}
+impl DynamicBody {
+    pub fn new(
+        position: BodyPosition,
+        velocity: BodyVelocity,
+        mass: BodyMass,
+        radius: BodyRadius,
+        color: Color,
+        name: String,
+    ) -> Self {
+        Self {
+            relative_position: position,
+            absolute_position: position,
+            relative_velocity: velocity,
+            absolute_velocity: velocity,
+            info: DynamicBodyInfo {
+                parent_generator: StaticGenerator::new(),
+                system_depth: 0,
+                soi_transitions: 0,
+
+                mass,
+                mu: mass * G,
+                mutual_gravity: false,
+                radius,
+                color,
+                name,
+
+                future_actions: FutureActions::new(),
+            }
+        }
+    }
+
+
+
+
+    /// Let this body pull on the other dynamic bodies in the same system. \
+    /// Off by default since it costs an extra pass over every body in the system for each body that has it.
+    pub fn with_mutual_gravity(mut self, mutual_gravity: bool) -> Self {
+        self.info.mutual_gravity = mutual_gravity;
+        self
+    }
+
+
+
+
+    ////////////////////////////// BUILDER METHODS //////////////////////////////
+    // These methods should only be used by the system builder to initialize bodies
 
-    previous_relative_velocity: BodyVelocity,
-    current_relative_velocity: BodyVelocity,
-    previous_absolute_velocity: BodyVelocity,
-    current_absolute_velocity: BodyVelocity,
+    /// Set the body's system_depth and parent_generator, then modify absolute position and velocity to reflect the change. \
+    /// The relative state is taken to be at the provided time.
+    pub fn initialize_in_system_tree(&mut self, system_depth: usize, parent_generator: &StaticGenerator, time: PhysicalTime) {
+        self.info.system_depth = system_depth;
 
+        self.info.parent_generator = parent_generator.clone();
+        let (parent_pos, parent_vel) = parent_generator.get_position_and_velocity(time);
+
+        self.absolute_position = parent_pos + self.relative_position;
+        self.absolute_velocity = parent_vel + self.relative_velocity;
+    }
+}
+
+
+/// Data of a dynamic body that is rarely needed while updating the tree
+#[derive(Clone, Debug)]
+struct DynamicBodyInfo {
     /// Static generator of the system that this body is currently in. \
     /// This should be used very sparingly since walking the position chain often is expensive. \
     parent_generator: StaticGenerator,
     /// How deep in the tree this body is. The length of the position chain is not reliable for this purpose.
     system_depth: usize,
+    /// Number of times this body has moved into a parent or child system
+    soi_transitions: u64,
 
     mass: BodyMass,
     mu: GravitationalParameter,
+    /// Whether the body pulls on the other dynamic bodies in its system
+    mutual_gravity: bool,
     radius: BodyRadius,
     color: Color,
     name: String,
 
-    gravitational_acceleration: BodyAcceleration,
     future_actions: FutureActions,
 }
-impl DynamicBody {
-    pub fn new(
-        position: BodyPosition,
-        velocity: BodyVelocity,
-        mass: BodyMass,
-        radius: BodyRadius,
-        color: Color,
-        name: String,
-    ) -> Self {
-        Self {
-            previous_relative_position: position,
-            current_relative_position: position,
-            previous_absolute_position: position,
-            current_absolute_position: position,
-            previous_relative_velocity: velocity,
-            current_relative_velocity: velocity,
-            previous_absolute_velocity: velocity,
-            current_absolute_velocity: velocity,
-
-            parent_generator: StaticGenerator::new(),
-            system_depth: 0,
-
-            mass,
-            mu: mass * G,
-            radius,
-            color,
-            name,
-
-            gravitational_acceleration: DVec2::ZERO,
-            future_actions: FutureActions::new(),
-        }
-    }
 
 
 
+/// All dynamic bodies of a body store, with one column per field. \
+/// The state that is read and written every tick is kept apart from the rest so the tree update only walks the memory it needs.
+#[derive(Clone, Debug, Default)]
+pub struct DynamicBodies {
+    previous_relative_position: Vec<BodyPosition>,
+    current_relative_position: Vec<BodyPosition>,
+    previous_absolute_position: Vec<BodyPosition>,
+    current_absolute_position: Vec<BodyPosition>,
 
+    previous_relative_velocity: Vec<BodyVelocity>,
+    current_relative_velocity: Vec<BodyVelocity>,
+    previous_absolute_velocity: Vec<BodyVelocity>,
+    current_absolute_velocity: Vec<BodyVelocity>,
 
+    gravitational_acceleration: Vec<BodyAcceleration>,
+    /// Gravitational parameter of bodies with mutual gravity, zero for every other body
+    mutual_mu: Vec<GravitationalParameter>,
 
-    ////////////////////////////// SYSTEM TREE METHODS //////////////////////////////
-    // These methods should only be used by the system tree to calculate acceleration and move bodies
+    info: Vec<DynamicBodyInfo>,
+}
+impl DynamicBodies {
+    /// Add a body and return its index
+    pub fn push(&mut self, body: DynamicBody) -> usize {
+        self.previous_relative_position.push(body.relative_position);
+        self.current_relative_position.push(body.relative_position);
+        self.previous_absolute_position.push(body.absolute_position);
+        self.current_absolute_position.push(body.absolute_position);
+        self.previous_relative_velocity.push(body.relative_velocity);
+        self.current_relative_velocity.push(body.relative_velocity);
+        self.previous_absolute_velocity.push(body.absolute_velocity);
+        self.current_absolute_velocity.push(body.absolute_velocity);
+        self.gravitational_acceleration.push(DVec2::ZERO);
+        self.mutual_mu.push(if body.info.mutual_gravity { body.info.mu } else { 0. });
+        self.info.push(body.info);
+        self.info.len()-1
+    }
 
-    /// Calculate the gravitational acceleration of the body using its current position and the position and gravitational parameter of the provided masses
-    pub fn calculate_gravitational_acceleration(&mut self, masses: &[(BodyPosition, GravitationalParameter)]) {
-        let mut accel = DVec2::ZERO;
-        let body_position = self.current_relative_position;
-        for (static_position, static_mu) in masses {
-            let dir = *static_position - body_position;
-            let norm = dir.length_squared();
-            accel += dir * (static_mu / (norm * norm.sqrt()));
-        }
-        self.gravitational_acceleration = accel
+    /// Remove the body at the index, moving the last body into its place
+    pub fn swap_remove(&mut self, index: usize) {
+        self.previous_relative_position.swap_remove(index);
+        self.current_relative_position.swap_remove(index);
+        self.previous_absolute_position.swap_remove(index);
+        self.current_absolute_position.swap_remove(index);
+        self.previous_relative_velocity.swap_remove(index);
+        self.current_relative_velocity.swap_remove(index);
+        self.previous_absolute_velocity.swap_remove(index);
+        self.current_absolute_velocity.swap_remove(index);
+        self.gravitational_acceleration.swap_remove(index);
+        self.mutual_mu.swap_remove(index);
+        self.info.swap_remove(index);
     }
 
-    /// Use the body's gravitational acceleration, velocity, and future actions to advance position 
-    pub fn accelerate_and_move_body(&mut self, new_time: DiscreteGravitySystemTime, should_accelerate: bool, parent_pos: BodyPosition, parent_vel: BodyVelocity, system_time_step: f64) {
-        let mut acceleration = self.future_actions.get_acceleration(new_time-1, self.mass);
-        if should_accelerate {
-            acceleration += self.gravitational_acceleration;
-        }
+    /// Replace the relative state of the body at the index, for example when it is edited by hand. \
+    /// The previous state is set as well so nothing is interpolated between the old and new state.
+    pub fn set_state(&mut self, index: usize, relative_position: BodyPosition, relative_velocity: BodyVelocity, parent_pos: BodyPosition, parent_vel: BodyVelocity) {
+        self.previous_relative_position[index] = relative_position;
+        self.current_relative_position[index] = relative_position;
+        self.previous_absolute_position[index] = relative_position + parent_pos;
+        self.current_absolute_position[index] = relative_position + parent_pos;
+        self.previous_relative_velocity[index] = relative_velocity;
+        self.current_relative_velocity[index] = relative_velocity;
+        self.previous_absolute_velocity[index] = relative_velocity + parent_vel;
+        self.current_absolute_velocity[index] = relative_velocity + parent_vel;
+    }
 
-        self.previous_relative_velocity = self.current_relative_velocity;
-        self.current_relative_velocity += acceleration * CALCULATION_TIME_STEP * system_time_step;
-        self.previous_relative_position = self.current_relative_position;
-        self.current_relative_position += self.current_relative_velocity * CALCULATION_TIME_STEP;
-
-        self.previous_absolute_position = self.current_absolute_position;
-        self.previous_absolute_velocity = self.current_absolute_velocity;
-        self.current_absolute_position = self.current_relative_position + parent_pos;
-        self.current_absolute_velocity = self.current_relative_velocity + parent_vel;
-
-        // Only rotate and scale gravitational acceleration vector if gravity will not be recalculated on the next time step
-        //if should_rotate_acceleration_vector {
-        //    // Get the scalar change in distance to the system center squared
-        //    // This is used to scale the acceleration vector as a body changes distance from the system center within iterations in a system's time_step
-        //    // Without this, elliptical orbits decay into circular ones
-        //    let distance_diff = self.previous_relative_position.length_squared() / self.current_relative_position.length_squared();
-        //    self.gravitational_acceleration = DVec2::from_angle(self.previous_relative_position.angle_between(self.current_relative_position)).rotate(self.gravitational_acceleration)*distance_diff;
-        //}
-    }
-
-    pub fn translate_to_parent(&mut self, time: GravitySystemTime) {
-        let (parent_pos, parent_vel) = self.parent_generator.pop_end().get_position_and_velocity(time);
-        self.current_relative_position += parent_pos;
-        self.previous_relative_position += parent_pos;
-        self.current_relative_velocity += parent_vel;
-        self.previous_relative_velocity += parent_vel;
-        self.system_depth -= 1;
-    }
-    pub fn translate_to_child(&mut self, time: GravitySystemTime, child_position: &StaticPosition) {
-        let (child_pos, child_vel) = child_position.get_position_and_velocity(time);
-        self.current_relative_position -= child_pos;
-        self.previous_relative_position -= child_pos;
-        self.current_relative_velocity -= child_vel;
-        self.previous_relative_velocity -= child_vel;
-        self.parent_generator.push_end(child_position.clone());
-        self.system_depth += 1;
+    pub fn len(&self) -> usize {
+        self.info.len()
     }
-    pub fn distance_squared(&self, other: BodyPosition) -> f64 {
-        self.current_relative_position.distance_squared(other)
+    pub fn is_empty(&self) -> bool {
+        self.info.is_empty()
     }
+
+    pub fn get(&self, index: usize) -> Option<DynamicBodyRef<'_>> {
+        (index < self.len()).then_some(DynamicBodyRef { bodies: self, index })
+    }
+    /// Same as get, but panics if the index is out of bounds
+    pub fn at(&self, index: usize) -> DynamicBodyRef<'_> {
+        assert!(index < self.len(), "dynamic body index out of bounds");
+        DynamicBodyRef { bodies: self, index }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = DynamicBodyRef<'_>> {
+        (0..self.len()).map(|index| DynamicBodyRef { bodies: self, index })
+    }
+
+    /// Copy the body at index into a new set of columns
+    pub fn retain_clone(&self, index: usize) -> Self {
+        self.retain_clone_many(&[index])
+    }
+    /// Copy the bodies at the indices into a new set of columns, in the order of the indices
+    pub fn retain_clone_many(&self, indices: &[usize]) -> Self {
+        fn pick<T: Clone>(column: &[T], indices: &[usize]) -> Vec<T> {
+            indices.iter().map(|i| column[*i].clone()).collect()
+        }
+        Self {
+            previous_relative_position: pick(&self.previous_relative_position, indices),
+            current_relative_position: pick(&self.current_relative_position, indices),
+            previous_absolute_position: pick(&self.previous_absolute_position, indices),
+            current_absolute_position: pick(&self.current_absolute_position, indices),
+            previous_relative_velocity: pick(&self.previous_relative_velocity, indices),
+            current_relative_velocity: pick(&self.current_relative_velocity, indices),
+            previous_absolute_velocity: pick(&self.previous_absolute_velocity, indices),
+            current_absolute_velocity: pick(&self.current_absolute_velocity, indices),
+            gravitational_acceleration: pick(&self.gravitational_acceleration, indices),
+            mutual_mu: pick(&self.mutual_mu, indices),
+            info: pick(&self.info, indices),
+        }
+    }
+
+    pub(super) fn as_ptr(&mut self) -> DynamicBodiesPtr<'_> {
+        DynamicBodiesPtr {
+            previous_relative_position: self.previous_relative_position.as_mut_ptr(),
+            current_relative_position: self.current_relative_position.as_mut_ptr(),
+            previous_absolute_position: self.previous_absolute_position.as_mut_ptr(),
+            current_absolute_position: self.current_absolute_position.as_mut_ptr(),
+            previous_relative_velocity: self.previous_relative_velocity.as_mut_ptr(),
+            current_relative_velocity: self.current_relative_velocity.as_mut_ptr(),
+            previous_absolute_velocity: self.previous_absolute_velocity.as_mut_ptr(),
+            current_absolute_velocity: self.current_absolute_velocity.as_mut_ptr(),
+            gravitational_acceleration: self.gravitational_acceleration.as_mut_ptr(),
+            mutual_mu: self.mutual_mu.as_mut_ptr(),
+            info: self.info.as_mut_ptr(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+
+
+/// Read only view of one body in DynamicBodies
+#[derive(Clone, Copy)]
+pub struct DynamicBodyRef<'a> {
+    bodies: &'a DynamicBodies,
+    index: usize,
+}
+impl<'a> DynamicBodyRef<'a> {
+    fn info(&self) -> &'a DynamicBodyInfo { &self.bodies.info[self.index] }
+
     /// Get the distance to the parent system center squared
     pub fn relative_magnitude_squared(&self) -> f64 {
-        self.current_relative_position.length_squared()
+        self.get_current_relative_position().length_squared()
     }
 
 
 
 
-
     ////////////////////////////// WRITER METHODS //////////////////////////////
     // These methods should only be used when writing data to visual objects
 
     pub fn get_interpolated_relative_position(&self, factor: f64) -> DVec2 {
-        self.previous_relative_position + ((self.current_relative_position-self.previous_relative_position)*factor)
+        let (previous, current) = (self.get_previous_relative_position(), self.get_current_relative_position());
+        previous + ((current-previous)*factor)
     }
     pub fn get_interpolated_absolute_position(&self, factor: f64) -> DVec2 {
-        self.previous_absolute_position + ((self.current_absolute_position-self.previous_absolute_position)*factor)
+        let (previous, current) = (self.get_previous_absolute_position(), self.get_current_absolute_position());
+        previous + ((current-previous)*factor)
     }
     pub fn get_interpolated_relative_velocity(&self, factor: f64) -> DVec2 {
-        self.previous_relative_velocity + ((self.current_relative_velocity-self.previous_relative_velocity)*factor)
+        let (previous, current) = (self.bodies.previous_relative_velocity[self.index], self.get_current_relative_velocity());
+        previous + ((current-previous)*factor)
     }
     pub fn get_interpolated_absolute_velocity(&self, factor: f64) -> DVec2 {
-        self.previous_absolute_velocity + ((self.current_absolute_velocity-self.previous_absolute_velocity)*factor)
+        let (previous, current) = (self.bodies.previous_absolute_velocity[self.index], self.get_current_absolute_velocity());
+        previous + ((current-previous)*factor)
     }
 
 
 
 
-    ////////////////////////////// BUILDER METHODS //////////////////////////////
-    // These methods should only be used by the system builder to initialize bodies
 
-    /// Set the body's system_depth and parent_generator, then modify absolute position and velocity to reflect the change
-    pub fn initialize_in_system_tree(&mut self, system_depth: usize, parent_generator: &StaticGenerator) {
-        self.system_depth = system_depth;
+    ////////////////////////////// GETTERS //////////////////////////////
+    pub fn get_mass(&self) -> BodyMass { self.info().mass }
+    pub fn get_mu(&self) -> GravitationalParameter { self.info().mu }
+    pub fn get_mutual_gravity(&self) -> bool { self.info().mutual_gravity }
+    pub fn get_radius(&self) -> BodyRadius { self.info().radius }
+    pub fn get_color(&self) -> Color { self.info().color }
+    pub fn get_name(&self) -> String { self.info().name.clone() }
+    pub fn get_system_depth(&self) -> usize { self.info().system_depth }
+    pub fn get_parent_generator(&self) -> &'a StaticGenerator { &self.info().parent_generator }
+    pub fn get_previous_relative_position(&self) -> BodyPosition { self.bodies.previous_relative_position[self.index] }
+    pub fn get_previous_absolute_position(&self) -> BodyPosition { self.bodies.previous_absolute_position[self.index] }
+    pub fn get_current_relative_position(&self) -> BodyPosition { self.bodies.current_relative_position[self.index] }
+    pub fn get_current_absolute_position(&self) -> BodyPosition { self.bodies.current_absolute_position[self.index] }
+    pub fn get_current_relative_velocity(&self) -> BodyVelocity { self.bodies.current_relative_velocity[self.index] }
+    pub fn get_current_absolute_velocity(&self) -> BodyVelocity { self.bodies.current_absolute_velocity[self.index] }
+    pub fn get_soi_transitions(&self) -> u64 { self.info().soi_transitions }
+}
 
-        self.parent_generator = parent_generator.clone();
-        let (parent_pos, parent_vel) = parent_generator.get_position_and_velocity(0.);
 
-        self.current_absolute_position = parent_pos + self.current_relative_position;
-        self.previous_absolute_position = parent_pos + self.previous_relative_position;
 
-        self.current_absolute_velocity = parent_vel + self.current_relative_velocity;
-        self.previous_absolute_velocity = parent_vel + self.previous_relative_velocity;
+/// Mutable handle to the columns of DynamicBodies that can be shared between threads while the tree is updated. \
+/// Every dynamic body index is in exactly one system, so threads working on different systems or different indices never alias.
+///
+/// # Safety
+/// Every unsafe method has the same requirements:
+/// - Every index passed in is less than the number of bodies the handle was made from.
+/// - No two threads use the same index at the same time, and nothing else reads or writes the body while a method uses it. \
+///   The system tree ensures this by only passing the indices of the system being updated.
+#[derive(Clone, Copy)]
+pub(super) struct DynamicBodiesPtr<'a> {
+    previous_relative_position: *mut BodyPosition,
+    current_relative_position: *mut BodyPosition,
+    previous_absolute_position: *mut BodyPosition,
+    current_absolute_position: *mut BodyPosition,
+    previous_relative_velocity: *mut BodyVelocity,
+    current_relative_velocity: *mut BodyVelocity,
+    previous_absolute_velocity: *mut BodyVelocity,
+    current_absolute_velocity: *mut BodyVelocity,
+    gravitational_acceleration: *mut BodyAcceleration,
+    mutual_mu: *mut GravitationalParameter,
+    info: *mut DynamicBodyInfo,
+    _marker: PhantomData<&'a mut DynamicBodies>,
+}
+unsafe impl Send for DynamicBodiesPtr<'_> {}
+unsafe impl Sync for DynamicBodiesPtr<'_> {}
+impl<'a> DynamicBodiesPtr<'a> {
+    ////////////////////////////// SYSTEM TREE METHODS //////////////////////////////
+    // These methods should only be used by the system tree to calculate acceleration and move bodies.
+    // See the safety section of DynamicBodiesPtr for what every unsafe method here requires.
+
+    /// Calculate the gravitational acceleration of the bodies using their current position and the position and gravitational parameter of the provided masses. \
+    /// Bodies are processed GRAVITY_LANES at a time so the inner loop over masses is vectorised.
+    pub unsafe fn calculate_gravitational_acceleration(self, indices: &[usize], masses: &[(BodyPosition, GravitationalParameter)]) {
+        let chunks = indices.chunks_exact(GRAVITY_LANES);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            self.calculate_gravitational_acceleration_batch::<GRAVITY_LANES>(chunk, masses);
+        }
+        for index in remainder {
+            self.calculate_gravitational_acceleration_batch::<1>(std::slice::from_ref(index), masses);
+        }
+    }
+    #[inline]
+    unsafe fn calculate_gravitational_acceleration_batch<const N: usize>(self, indices: &[usize], masses: &[(BodyPosition, GravitationalParameter)]) {
+        let positions: [BodyPosition; N] = std::array::from_fn(|lane| *self.current_relative_position.add(indices[lane]));
+        let (accel_x, accel_y) = gravity_kernel(&positions.map(|p| p.x), &positions.map(|p| p.y), masses);
+        for (lane, index) in indices.iter().enumerate() {
+            *self.gravitational_acceleration.add(*index) = DVec2::new(accel_x[lane], accel_y[lane]);
+        }
+    }
+
+    /// Position and gravitational parameter of the body if it has mutual gravity
+    #[inline]
+    pub unsafe fn get_mutual_mass(self, index: usize) -> Option<(BodyPosition, GravitationalParameter)> {
+        let mu = *self.mutual_mu.add(index);
+        (mu != 0.).then(|| (*self.current_relative_position.add(index), mu))
     }
 
+    /// Add the pull of other dynamic bodies in the system to the body's gravitational acceleration. \
+    /// Masses are (body index, position, gravitational parameter) and the body itself is skipped if it is one of them.
+    pub unsafe fn add_mutual_gravitational_acceleration(self, index: usize, masses: &[(usize, BodyPosition, GravitationalParameter)]) {
+        let body_position = *self.current_relative_position.add(index);
+        let mut accel = DVec2::ZERO;
+        for (other_index, other_position, other_mu) in masses {
+            if *other_index == index { continue }
+            let dir = *other_position - body_position;
+            let norm = dir.length_squared();
+            accel += dir * (other_mu / (norm * norm.sqrt()));
+        }
+        *self.gravitational_acceleration.add(index) += accel;
+    }
 
+    /// Add the pull of other dynamic bodies in the system, approximated by a Barnes-Hut tree, to the body's gravitational acceleration
+    pub unsafe fn add_tree_gravitational_acceleration(self, index: usize, tree: &BarnesHutTree, opening_angle: f64) {
+        let body_position = *self.current_relative_position.add(index);
+        *self.gravitational_acceleration.add(index) += tree.get_acceleration(index, body_position, opening_angle);
+    }
 
+    /// Use the body's gravitational acceleration, velocity, and future actions to advance position by one tick of tick_length seconds
+    #[inline]
+    pub unsafe fn accelerate_and_move_body(self, index: usize, tick: &TickContext, should_accelerate: bool, system_time_step: f64) {
+        let TickContext { new_time, tick_length, parent_pos, parent_vel } = *tick;
+        let info = &mut *self.info.add(index);
+        let mut acceleration = info.future_actions.get_acceleration(new_time-1, info.mass);
+        if should_accelerate {
+            acceleration += *self.gravitational_acceleration.add(index);
+        }
 
+        let relative_velocity = &mut *self.current_relative_velocity.add(index);
+        let relative_position = &mut *self.current_relative_position.add(index);
+        *self.previous_relative_velocity.add(index) = *relative_velocity;
+        *relative_velocity += acceleration * tick_length * system_time_step;
+        *self.previous_relative_position.add(index) = *relative_position;
+        *relative_position += *relative_velocity * tick_length;
+
+        let absolute_position = &mut *self.current_absolute_position.add(index);
+        let absolute_velocity = &mut *self.current_absolute_velocity.add(index);
+        *self.previous_absolute_position.add(index) = *absolute_position;
+        *self.previous_absolute_velocity.add(index) = *absolute_velocity;
+        *absolute_position = *relative_position + parent_pos;
+        *absolute_velocity = *relative_velocity + parent_vel;
+    }
 
-    ////////////////////////////// GETTERS //////////////////////////////
-    pub fn get_mass(&self) -> BodyMass { self.mass }
-    pub fn get_radius(&self) -> BodyRadius { self.radius }
-    pub fn get_color(&self) -> Color { self.color }
-    pub fn get_name(&self) -> String { self.name.clone() }
-    pub fn get_system_depth(&self) -> usize { self.system_depth }
-    pub fn get_parent_generator(&self) -> &StaticGenerator { &self.parent_generator }
-    pub fn get_previous_relative_position(&self) -> BodyPosition { self.previous_relative_position }
-    pub fn get_previous_absolute_position(&self) -> BodyPosition { self.previous_absolute_position }
-}
\ No newline at end of file
+    pub unsafe fn translate_to_parent(self, index: usize, time: PhysicalTime) {
+        let info = &mut *self.info.add(index);
+        let (parent_pos, parent_vel) = info.parent_generator.pop_end().get_position_and_velocity(time);
+        self.offset_relative_state(index, parent_pos, parent_vel);
+        info.system_depth -= 1;
+        info.soi_transitions += 1;
+    }
+    pub unsafe fn translate_to_child(self, index: usize, time: PhysicalTime, child_position: &StaticPosition) {
+        let info = &mut *self.info.add(index);
+        let (child_pos, child_vel) = child_position.get_position_and_velocity(time);
+        self.offset_relative_state(index, -child_pos, -child_vel);
+        info.parent_generator.push_end(child_position.clone());
+        info.system_depth += 1;
+        info.soi_transitions += 1;
+    }
+    #[inline]
+    unsafe fn offset_relative_state(self, index: usize, position: BodyPosition, velocity: BodyVelocity) {
+        *self.current_relative_position.add(index) += position;
+        *self.previous_relative_position.add(index) += position;
+        *self.current_relative_velocity.add(index) += velocity;
+        *self.previous_relative_velocity.add(index) += velocity;
+    }
+
+    pub unsafe fn get_relative_position(self, index: usize) -> BodyPosition {
+        *self.current_relative_position.add(index)
+    }
+    /// Get the distance to the parent system center squared
+    pub unsafe fn relative_magnitude_squared(self, index: usize) -> f64 {
+        (*self.current_relative_position.add(index)).length_squared()
+    }
+}
+
+
+/// Gravitational acceleration at N positions from all masses. \
+/// Does the same operations in the same order as summing body by body, so results are identical for any N.
+#[inline]
+fn gravity_kernel<const N: usize>(x: &[f64; N], y: &[f64; N], masses: &[(BodyPosition, GravitationalParameter)]) -> ([f64; N], [f64; N]) {
+    let mut accel_x = [0.; N];
+    let mut accel_y = [0.; N];
+    for (static_position, static_mu) in masses {
+        for lane in 0..N {
+            let dir_x = static_position.x - x[lane];
+            let dir_y = static_position.y - y[lane];
+            let norm = dir_x * dir_x + dir_y * dir_y;
+            let scale = static_mu / (norm * norm.sqrt());
+            accel_x[lane] += dir_x * scale;
+            accel_y[lane] += dir_y * scale;
+        }
+    }
+    (accel_x, accel_y)
+}
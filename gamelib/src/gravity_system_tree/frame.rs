@@ -0,0 +1,102 @@
+use bevy::math::DVec2;
+
+use super::{body_id::{BodyId, SystemId}, static_body::StaticPosition, static_generator::StaticGenerator, system_tree::PhysicalTime, BodyPosition};
+
+
+
+/// Frame that positions along predicted paths are shown in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReferenceFrame {
+    /// Every part of a path relative to the system it is in, with the system placed where it is now
+    #[default]
+    Local,
+    /// Positions relative to the root system, which never moves
+    Inertial,
+    /// Positions relative to the center of a system
+    System(SystemId),
+    /// Positions relative to the center that a static body orbits, turning with the body so that it stays in place. \
+    /// Lagrange points of the body and its center stay in place too.
+    CoRotating(BodyId),
+}
+
+
+
+/// Origin and orientation of a reference frame over time. \
+/// Positions are moved into the frame at the time they belong to, then drawn with the frame where it is at the current time.
+#[derive(Clone, Debug, Default)]
+pub struct FrameTransform {
+    origin: StaticGenerator,
+    /// Orbit around the origin that the frame turns with
+    rotation: Option<StaticPosition>,
+}
+impl FrameTransform {
+    pub fn new(origin: StaticGenerator, rotation: Option<StaticPosition>) -> Self {
+        let rotation = rotation.filter(|r| *r != StaticPosition::Still);
+        Self { origin, rotation }
+    }
+
+    /// Position in the frame of an absolute position at a time
+    pub fn to_frame(&self, position: BodyPosition, time: PhysicalTime) -> BodyPosition {
+        let relative = position - self.origin.get_position(time);
+        match &self.rotation {
+            Some(rotation) => DVec2::from_angle(-get_angle(rotation, time)).rotate(relative),
+            None => relative,
+        }
+    }
+
+    /// Where the frame is at a time
+    pub fn get_placement(&self, time: PhysicalTime) -> FramePlacement {
+        FramePlacement {
+            origin: self.origin.get_position(time),
+            rotation: DVec2::from_angle(self.rotation.as_ref().map_or(0., |r| get_angle(r, time))),
+        }
+    }
+
+    /// Absolute position to draw an absolute position at a time, with the frame placed where it is at another time
+    pub fn project(&self, position: BodyPosition, time: PhysicalTime, placement: &FramePlacement) -> BodyPosition {
+        placement.from_frame(self.to_frame(position, time))
+    }
+}
+
+fn get_angle(rotation: &StaticPosition, time: PhysicalTime) -> f64 {
+    rotation.get_polar_position(time)[1]
+}
+
+
+
+/// Origin and orientation of a frame at one time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FramePlacement {
+    origin: BodyPosition,
+    /// Unit vector of the frame's x axis
+    rotation: DVec2,
+}
+impl FramePlacement {
+    /// Absolute position of a position in the frame
+    pub fn from_frame(&self, position: BodyPosition) -> BodyPosition {
+        self.origin + self.rotation.rotate(position)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbiting_body_stays_in_place_when_co_rotating() {
+        let mut origin = StaticGenerator::new();
+        origin.push_end(StaticPosition::Circular { radius: 1000., speed: 0.01, start_angle: 0. });
+        let orbit = StaticPosition::Circular { radius: 50., speed: 0.3, start_angle: 1. };
+        let frame = FrameTransform::new(origin.clone(), Some(orbit.clone()));
+
+        let placement = frame.get_placement(0.);
+        let start = origin.get_position(0.) + orbit.get_position(0.);
+        for time in [0., 3., 17.5, 400.] {
+            let body = origin.get_position(time) + orbit.get_position(time);
+            assert!(frame.project(body, time, &placement).distance(start) < 1e-9);
+        }
+    }
+}
@@ -8,6 +8,14 @@ pub mod generate;
 pub mod system_tree;
 pub mod future_actions;
 pub mod static_generator;
+pub mod body_id;
+pub mod scenario;
+pub mod child_grid;
+pub mod solver;
+pub mod reference;
+pub mod collision;
+pub mod calendar;
+pub mod frame;
 
 
 type BodyPosition = DVec2;
@@ -17,7 +25,8 @@ type GravitationalParameter = f64;
 type BodyMass = f64;
 type BodyRadius = f64;
 
-pub const CALCULATION_TIME_STEP: f64 = 0.0001;
+/// Physical seconds per tick of a system manager that does not set its own tick length
+pub const DEFAULT_TICK_LENGTH: f64 = 0.0001;
 
 /*
 Gravitational acceleration will be updated based on the time step of individual bodies and stored in a map.
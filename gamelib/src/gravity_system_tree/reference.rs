@@ -0,0 +1,204 @@
+use bevy::math::DVec2;
+use rayon::prelude::*;
+
+use super::{body_id::BodyId, builder::{GravitySystemBuilder, SystemTreeError}, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{DiscreteGravitySystemTime, GravitySystemTime}, BodyAcceleration, BodyPosition, BodyVelocity, GravitationalParameter};
+
+
+/// Engines with at least this many dynamic bodies calculate accelerations in parallel
+const PARALLEL_BODY_THRESHOLD: usize = 256;
+
+
+
+/// Flat N-body simulation of the bodies in a gravity system tree, used to measure how far the tree drifts from the true motion. \
+/// Every dynamic body is pulled by every static body and every dynamic body with mutual gravity, no matter which system either is in. \
+/// Static bodies follow the same rails as in the tree, so the difference comes from systems only seeing their own masses, system time steps and the integrator. \
+/// Bodies are advanced with classic fourth order Runge-Kutta, with a configurable number of substeps per tick.
+#[derive(Clone, Debug)]
+pub struct ReferenceEngine {
+    /// Rail of each static body, including the body's own static position
+    static_rails: Vec<StaticGenerator>,
+    static_mus: Vec<GravitationalParameter>,
+    /// Ids of the dynamic bodies, in the same order as positions and velocities
+    ids: Vec<BodyId>,
+    positions: Vec<BodyPosition>,
+    velocities: Vec<BodyVelocity>,
+    /// Gravitational parameter of bodies with mutual gravity, zero for the rest
+    mutual_mus: Vec<GravitationalParameter>,
+    substeps: u32,
+    /// Physical seconds per tick, taken from the manager
+    tick_length: f64,
+    current_time: DiscreteGravitySystemTime,
+}
+impl ReferenceEngine {
+    /// Build the tree described by the builder and flatten it
+    pub fn new(builder: GravitySystemBuilder) -> Result<Self, SystemTreeError> {
+        Ok(Self::from_manager(&GravitySystemManager::try_new(builder)?))
+    }
+
+    /// Flatten the bodies of the manager at its current time
+    pub fn from_manager(manager: &GravitySystemManager) -> Self {
+        let store = &manager.body_store;
+        let static_rails = store.static_bodies.iter().map(|body| {
+            let mut rail = body.get_parent_generator().clone();
+            rail.push_end(body.get_static_position().clone());
+            rail
+        }).collect();
+        Self {
+            static_rails,
+            static_mus: store.static_bodies.iter().map(|b| b.get_mu()).collect(),
+            ids: store.dynamic_ids.clone(),
+            positions: store.dynamic_bodies.iter().map(|b| b.get_current_absolute_position()).collect(),
+            velocities: store.dynamic_bodies.iter().map(|b| b.get_current_absolute_velocity()).collect(),
+            mutual_mus: store.dynamic_bodies.iter().map(|b| if b.get_mutual_gravity() { b.get_mu() } else { 0. }).collect(),
+            substeps: 1,
+            tick_length: manager.get_tick_length(),
+            current_time: manager.get_current_time(),
+        }
+    }
+
+    /// Split every tick into this many integration steps. More substeps are slower but more accurate.
+    pub fn with_substeps(mut self, substeps: u32) -> Self {
+        self.substeps = substeps.max(1);
+        self
+    }
+
+    /// Advance every dynamic body by one tick
+    pub fn step(&mut self) {
+        let tick_fraction = 1. / self.substeps as f64;
+        for substep in 0..self.substeps {
+            let time = self.current_time as GravitySystemTime + substep as f64 * tick_fraction;
+            self.runge_kutta_step(time, tick_fraction);
+        }
+        self.current_time += 1;
+    }
+
+    /// One fourth order Runge-Kutta step of ticks length, starting at time
+    fn runge_kutta_step(&mut self, time: GravitySystemTime, ticks: f64) {
+        let h = ticks * self.tick_length;
+        let offset = |base: &[DVec2], rate: &[DVec2], scale: f64| base.iter().zip(rate).map(|(b, r)| *b + *r * scale).collect::<Vec<_>>();
+
+        let k1_velocity = self.velocities.clone();
+        let k1_acceleration = self.get_accelerations(time, &self.positions);
+        let k2_velocity = offset(&self.velocities, &k1_acceleration, h / 2.);
+        let k2_acceleration = self.get_accelerations(time + ticks / 2., &offset(&self.positions, &k1_velocity, h / 2.));
+        let k3_velocity = offset(&self.velocities, &k2_acceleration, h / 2.);
+        let k3_acceleration = self.get_accelerations(time + ticks / 2., &offset(&self.positions, &k2_velocity, h / 2.));
+        let k4_velocity = offset(&self.velocities, &k3_acceleration, h);
+        let k4_acceleration = self.get_accelerations(time + ticks, &offset(&self.positions, &k3_velocity, h));
+
+        for i in 0..self.positions.len() {
+            self.positions[i] += (k1_velocity[i] + 2. * k2_velocity[i] + 2. * k3_velocity[i] + k4_velocity[i]) * (h / 6.);
+            self.velocities[i] += (k1_acceleration[i] + 2. * k2_acceleration[i] + 2. * k3_acceleration[i] + k4_acceleration[i]) * (h / 6.);
+        }
+    }
+
+    /// Acceleration of every dynamic body at the provided positions, with static bodies placed at time
+    fn get_accelerations(&self, time: GravitySystemTime, positions: &[BodyPosition]) -> Vec<BodyAcceleration> {
+        let masses = self.static_rails
+            .iter()
+            .zip(&self.static_mus)
+            .map(|(rail, mu)| (usize::MAX, rail.get_position(time * self.tick_length), *mu))
+            .chain(positions.iter().zip(&self.mutual_mus).enumerate().filter(|(_, (_, mu))| **mu != 0.).map(|(i, (p, mu))| (i, *p, *mu)))
+            .collect::<Vec<_>>();
+        let acceleration = |(index, position): (usize, &BodyPosition)| {
+            masses.iter().filter(|(i, ..)| *i != index).fold(DVec2::ZERO, |accel, (_, p, mu)| {
+                let dir = *p - *position;
+                let norm = dir.length_squared();
+                accel + dir * (mu / (norm * norm.sqrt()))
+            })
+        };
+        if positions.len() >= PARALLEL_BODY_THRESHOLD {
+            positions.par_iter().enumerate().map(acceleration).collect()
+        } else {
+            positions.iter().enumerate().map(acceleration).collect()
+        }
+    }
+
+
+    ////////////////////////////// GETTERS //////////////////////////////
+    pub fn get_current_time(&self) -> DiscreteGravitySystemTime { self.current_time }
+    pub fn get_ids(&self) -> &[BodyId] { &self.ids }
+    pub fn get_position(&self, id: BodyId) -> Option<BodyPosition> {
+        self.ids.iter().position(|i| *i == id).map(|i| self.positions[i])
+    }
+    pub fn get_velocity(&self, id: BodyId) -> Option<BodyVelocity> {
+        self.ids.iter().position(|i| *i == id).map(|i| self.velocities[i])
+    }
+}
+
+
+/// Position error of a tree simulation compared to a reference engine at the same tick
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PositionErrorSample {
+    pub time: DiscreteGravitySystemTime,
+    /// Distance between the tree position and the reference position of each body that exists in both
+    pub errors: Vec<(BodyId, f64)>,
+    pub max: f64,
+    pub mean: f64,
+    /// Root mean square of the errors
+    pub rms: f64,
+}
+impl PositionErrorSample {
+    /// Compare the absolute positions of dynamic bodies. Both simulations should be at the same tick.
+    pub fn new(manager: &GravitySystemManager, reference: &ReferenceEngine) -> Self {
+        let store = &manager.body_store;
+        let errors = reference.ids
+            .iter()
+            .zip(&reference.positions)
+            .filter_map(|(id, position)| Some((*id, store.get_dynamic_body(*id)?.get_current_absolute_position().distance(*position))))
+            .collect::<Vec<_>>();
+        let count = errors.len().max(1) as f64;
+        Self {
+            time: manager.get_current_time(),
+            max: errors.iter().fold(0., |max, (_, e)| f64::max(max, *e)),
+            mean: errors.iter().map(|(_, e)| e).sum::<f64>() / count,
+            rms: (errors.iter().map(|(_, e)| e * e).sum::<f64>() / count).sqrt(),
+            errors,
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::Color;
+    use crate::{gravity_system_tree::{dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}}, G};
+    use super::*;
+
+    #[test]
+    fn reference_matches_tree_in_single_system() {
+        let mass = 1e20;
+        let radius = 1000.;
+        let speed = (mass * G / radius).sqrt();
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e6)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, mass, 1., Color::WHITE, "".into())])
+            .with_dynamic_bodies(&[DynamicBody::new(DVec2::X * radius, DVec2::Y * speed, 1., 1., Color::WHITE, "".into())]);
+        let mut manager = GravitySystemManager::new(system.clone());
+        let mut reference = ReferenceEngine::new(system).unwrap();
+        let id = reference.get_ids()[0];
+
+        let mut samples = vec![];
+        for tick in 1..=10_000 {
+            manager.step();
+            reference.step();
+            if tick % 1000 == 0 {
+                samples.push(PositionErrorSample::new(&manager, &reference));
+            }
+        }
+
+        // The circular orbit is kept almost exactly
+        let distance = reference.get_position(id).unwrap().length();
+        assert!((distance - radius).abs() < radius * 1e-9, "{distance}");
+        // The tree only differs by its integration error, which is small and nonzero
+        let last = samples.last().unwrap();
+        assert_eq!(last.time, 10_000);
+        assert_eq!(last.errors.len(), 1);
+        assert!(last.max > 0. && last.max < radius * 1e-2, "{}", last.max);
+        assert_eq!(last.max, last.rms);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{fs, path::Path};
+
+use bevy::{color::Color, math::DVec2};
+use serde::{Deserialize, Serialize};
+
+use super::{calendar::Epoch, builder::{GravitySystemBuilder, SystemTreeError}, collision::CollisionSettings, dynamic_body::DynamicBody, solver::GravitySolver, static_body::{StaticBody, StaticPosition}, system_manager::GravitySystemManager, DEFAULT_TICK_LENGTH};
+
+
+
+/// Serializable description of a gravity system tree. \
+/// Scenario files can be written as RON or JSON, the format is picked from the file extension.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub system: SystemDescription,
+    /// Physical seconds per tick
+    #[serde(default = "default_tick_length")]
+    pub tick_length: f64,
+    /// Date of time zero, or no calendar when missing
+    #[serde(default)]
+    pub epoch: Epoch,
+    /// Bodies pass through each other when there are no collision settings
+    #[serde(default)]
+    pub collisions: Option<CollisionSettings>,
+}
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        if is_json(path) {
+            serde_json::from_str(&data).map_err(|e| ScenarioError::Parse(e.to_string()))
+        } else {
+            ron::from_str(&data).map_err(|e| ScenarioError::Parse(e.to_string()))
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ScenarioError> {
+        let path = path.as_ref();
+        let data = if is_json(path) {
+            serde_json::to_string_pretty(self).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        };
+        fs::write(path, data).map_err(ScenarioError::Io)
+    }
+
+    pub fn to_builder(&self) -> GravitySystemBuilder {
+        self.system.to_builder()
+    }
+
+    pub fn build_manager(&self) -> Result<GravitySystemManager, ScenarioError> {
+        if !(self.tick_length > 0. && self.tick_length.is_finite()) {
+            return Err(ScenarioError::InvalidTickLength(self.tick_length))
+        }
+        GravitySystemManager::try_new(self.to_builder())
+            .map(|m| m.with_tick_length(self.tick_length).with_epoch(self.epoch.clone()).with_collision_settings(self.collisions.clone()))
+            .map_err(ScenarioError::Build)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+}
+
+fn default_tick_length() -> f64 { DEFAULT_TICK_LENGTH }
+
+
+/// A system and everything in it. Positions and velocities of bodies are relative to the system center.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SystemDescription {
+    pub position: StaticPosition,
+    pub radius: f64,
+    pub time_step: u64,
+    #[serde(default)]
+    pub static_bodies: Vec<StaticBodyDescription>,
+    #[serde(default)]
+    pub dynamic_bodies: Vec<DynamicBodyDescription>,
+    #[serde(default)]
+    pub children: Vec<SystemDescription>,
+    /// How dynamic bodies with mutual gravity pull on each other
+    #[serde(default)]
+    pub solver: GravitySolver,
+}
+impl SystemDescription {
+    pub fn to_builder(&self) -> GravitySystemBuilder {
+        let static_bodies = self.static_bodies.iter().map(|b| b.to_body()).collect::<Vec<_>>();
+        let dynamic_bodies = self.dynamic_bodies.iter().map(|b| b.to_body()).collect::<Vec<_>>();
+        let children = self.children.iter().map(|c| c.to_builder()).collect::<Vec<_>>();
+        GravitySystemBuilder::new()
+            .with_position(self.position.clone())
+            .with_radius(self.radius)
+            .with_time_step(self.time_step)
+            .with_static_bodies(&static_bodies)
+            .with_dynamic_bodies(&dynamic_bodies)
+            .with_children(&children)
+            .with_solver(self.solver)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StaticBodyDescription {
+    pub name: String,
+    pub position: StaticPosition,
+    pub mass: f64,
+    pub radius: f64,
+    /// Linear rgb
+    pub color: [f32; 3],
+}
+impl StaticBodyDescription {
+    pub fn to_body(&self) -> StaticBody {
+        StaticBody::new(self.position.clone(), self.mass, self.radius, Color::linear_rgb(self.color[0], self.color[1], self.color[2]), self.name.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DynamicBodyDescription {
+    pub name: String,
+    pub position: DVec2,
+    pub velocity: DVec2,
+    pub mass: f64,
+    pub radius: f64,
+    /// Linear rgb
+    pub color: [f32; 3],
+    /// Whether the body pulls on the other dynamic bodies in its system
+    #[serde(default)]
+    pub mutual_gravity: bool,
+}
+impl DynamicBodyDescription {
+    pub fn to_body(&self) -> DynamicBody {
+        DynamicBody::new(self.position, self.velocity, self.mass, self.radius, Color::linear_rgb(self.color[0], self.color[1], self.color[2]), self.name.clone())
+            .with_mutual_gravity(self.mutual_gravity)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    /// The scenario file could not be read or written
+    Io(std::io::Error),
+    /// The scenario file is not a valid scenario
+    Parse(String),
+    /// The scenario describes a system tree that can't be built
+    Build(SystemTreeError),
+    /// Ticks must be a positive, finite number of seconds long
+    InvalidTickLength(f64),
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn earth_moon() -> Scenario {
+        Scenario::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/scenarios/earth_moon.ron")).unwrap()
+    }
+
+    #[test]
+    fn earth_moon_scenario_builds() {
+        let scenario = earth_moon();
+        assert_eq!(scenario.name, "Earth and Moon");
+        assert_eq!(scenario.tick_length, DEFAULT_TICK_LENGTH);
+        assert!(scenario.collisions.is_none());
+
+        let manager = scenario.build_manager().unwrap();
+        assert_eq!(manager.get_epoch(), &scenario.epoch);
+        let names = manager.body_store.static_bodies.iter().map(|b| b.get_name()).collect::<Vec<_>>();
+        assert_eq!(names, ["Earth", "Moon"]);
+        assert_eq!(manager.body_store.dynamic_bodies.at(0).get_name(), "Satellite");
+    }
+
+    #[test]
+    fn scenario_round_trips_through_ron_and_json() {
+        let scenario = earth_moon();
+        let expected = ron::to_string(&scenario).unwrap();
+        for extension in ["ron", "json"] {
+            let path = std::env::temp_dir().join(format!("scenario_round_trip_{}.{extension}", std::process::id()));
+            scenario.save(&path).unwrap();
+            let loaded = Scenario::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(ron::to_string(&loaded.unwrap()).unwrap(), expected, "{extension}");
+        }
+    }
+
+    #[test]
+    fn build_manager_rejects_invalid_tick_length() {
+        let mut scenario = earth_moon();
+        for tick_length in [0., -1., f64::NAN, f64::INFINITY] {
+            scenario.tick_length = tick_length;
+            assert!(matches!(scenario.build_manager(), Err(ScenarioError::InvalidTickLength(_))), "{tick_length}");
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use bevy::math::DVec2;
+use serde::{Deserialize, Serialize};
+
+use super::{BodyAcceleration, BodyPosition, GravitationalParameter};
+
+
+/// Leaves are split once they hold more bodies than this
+const LEAF_CAPACITY: usize = 8;
+/// Leaves at this depth are never split, so bodies at the same position can't recurse forever
+const MAX_DEPTH: usize = 48;
+
+
+
+/// How a system calculates the pull that dynamic bodies with mutual gravity have on each other. \
+/// Static masses are always summed directly since there are few of them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum GravitySolver {
+    /// Sum the pull of every body directly. Exact, but the cost grows with the square of the number of massive bodies.
+    #[default]
+    Direct,
+    /// Approximate groups of distant bodies by their center of mass using a Barnes-Hut quadtree. \
+    /// A group is approximated when its size divided by its distance is below opening_angle. \
+    /// Zero opens every group, around 0.5 is a common trade off, and larger values are faster but less accurate.
+    BarnesHut { opening_angle: f64 },
+}
+
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    /// Center of the square the node covers
+    center: DVec2,
+    half_size: f64,
+    mu: GravitationalParameter,
+    center_of_mass: BodyPosition,
+    /// Index of the first of four children, or 0 for leaves
+    children: usize,
+    /// Range of order that holds the bodies of a leaf
+    start: usize,
+    end: usize,
+}
+
+
+/// Quadtree over the dynamic bodies of a system that have mutual gravity. \
+/// Rebuilt every time gravity is calculated, buffers are reused between rebuilds.
+#[derive(Clone, Debug, Default)]
+pub struct BarnesHutTree {
+    /// Body index, position and gravitational parameter of every body in the tree
+    masses: Vec<(usize, BodyPosition, GravitationalParameter)>,
+    /// Indices into masses, grouped so that each leaf's bodies are contiguous
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+}
+impl BarnesHutTree {
+    pub fn rebuild(&mut self, masses: &[(usize, BodyPosition, GravitationalParameter)]) {
+        self.masses.clear();
+        self.masses.extend_from_slice(masses);
+        self.order.clear();
+        self.order.extend(0..masses.len());
+        self.nodes.clear();
+        if masses.is_empty() { return }
+
+        let (min, max) = masses.iter().fold((DVec2::INFINITY, DVec2::NEG_INFINITY), |(min, max), (_, p, _)| (min.min(*p), max.max(*p)));
+        self.nodes.push(Node {
+            center: (min + max) / 2.,
+            half_size: (max - min).max_element() / 2.,
+            ..Default::default()
+        });
+        self.build_node(0, 0, masses.len(), 0);
+    }
+
+    fn build_node(&mut self, node: usize, start: usize, end: usize, depth: usize) {
+        let (mu, weighted_position) = self.order[start..end]
+            .iter()
+            .map(|i| self.masses[*i])
+            .fold((0., DVec2::ZERO), |(mu, weighted), (_, p, m)| (mu + m, weighted + p * m));
+        let center = self.nodes[node].center;
+        let half_size = self.nodes[node].half_size;
+        self.nodes[node].mu = mu;
+        self.nodes[node].center_of_mass = if mu != 0. { weighted_position / mu } else { center };
+        self.nodes[node].start = start;
+        self.nodes[node].end = end;
+        if end - start <= LEAF_CAPACITY || depth == MAX_DEPTH { return }
+
+        // Group bodies by quadrant, keeping their relative order
+        let quadrant = |p: BodyPosition| (p.x >= center.x) as usize + 2 * (p.y >= center.y) as usize;
+        let masses = &self.masses;
+        self.order[start..end].sort_by_key(|i| quadrant(masses[*i].1));
+
+        let children = self.nodes.len();
+        self.nodes[node].children = children;
+        let quarter = half_size / 2.;
+        for q in 0..4 {
+            let offset = DVec2::new(if q & 1 == 1 { quarter } else { -quarter }, if q & 2 == 2 { quarter } else { -quarter });
+            self.nodes.push(Node { center: center + offset, half_size: quarter, ..Default::default() });
+        }
+        let mut child_start = start;
+        for q in 0..4 {
+            let child_end = child_start + self.order[child_start..end].iter().take_while(|i| quadrant(self.masses[**i].1) == q).count();
+            self.build_node(children + q, child_start, child_end, depth + 1);
+            child_start = child_end;
+        }
+    }
+
+    /// Acceleration at the position from every body in the tree except the body with the provided index
+    pub fn get_acceleration(&self, index: usize, position: BodyPosition, opening_angle: f64) -> BodyAcceleration {
+        let mut accel = DVec2::ZERO;
+        if !self.nodes.is_empty() {
+            self.accumulate(0, index, position, opening_angle.powi(2), &mut accel);
+        }
+        accel
+    }
+
+    fn accumulate(&self, node: usize, index: usize, position: BodyPosition, opening_angle_squared: f64, accel: &mut BodyAcceleration) {
+        let node = &self.nodes[node];
+        if node.mu == 0. { return }
+
+        if node.children == 0 {
+            for (other_index, other_position, other_mu) in self.order[node.start..node.end].iter().map(|i| &self.masses[*i]) {
+                if *other_index == index { continue }
+                let dir = *other_position - position;
+                let norm = dir.length_squared();
+                *accel += dir * (other_mu / (norm * norm.sqrt()));
+            }
+            return
+        }
+
+        // Nodes that contain the position are always opened so a body never pulls on itself
+        let dir = node.center_of_mass - position;
+        let norm = dir.length_squared();
+        let contains = (position - node.center).abs().max_element() <= node.half_size;
+        if !contains && (2. * node.half_size).powi(2) < opening_angle_squared * norm {
+            *accel += dir * (node.mu / (norm * norm.sqrt()));
+            return
+        }
+        for child in node.children..node.children+4 {
+            self.accumulate(child, index, position, opening_angle_squared, accel);
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use super::*;
+
+    fn direct(masses: &[(usize, BodyPosition, GravitationalParameter)], index: usize, position: BodyPosition) -> BodyAcceleration {
+        masses.iter().filter(|(i, ..)| *i != index).fold(DVec2::ZERO, |accel, (_, p, mu)| {
+            let dir = *p - position;
+            let norm = dir.length_squared();
+            accel + dir * (mu / (norm * norm.sqrt()))
+        })
+    }
+
+    #[test]
+    fn barnes_hut_approximates_direct_sum() {
+        let mut rng = StdRng::seed_from_u64(2);
+        // A dense cluster, a sparse halo and a clump too tight to split before the depth limit
+        let mut masses = (0..2000).map(|i| {
+            let radius = if i < 1500 { rng.gen_range(0. ..100.) } else { rng.gen_range(100. ..10_000.) };
+            (i * 3, DVec2::from_angle(rng.gen_range(0. ..std::f64::consts::TAU)) * radius, rng.gen_range(1. ..10.))
+        }).collect::<Vec<_>>();
+        masses.extend((0..LEAF_CAPACITY+2).map(|i| (9000 + i, DVec2::new(5., 5. + i as f64 * 1e-12), 1.)));
+
+        let mut tree = BarnesHutTree::default();
+        tree.rebuild(&masses);
+        let mut errors = Vec::new();
+        for (index, position, _) in &masses {
+            let expected = direct(&masses, *index, *position);
+            let exact = tree.get_acceleration(*index, *position, 0.);
+            assert!((exact - expected).length() <= expected.length() * 1e-9, "{exact} {expected}");
+            let approximate = tree.get_acceleration(*index, *position, 0.5);
+            errors.push((approximate - expected).length() / expected.length());
+        }
+        // Bodies where the pulls almost cancel out can have a large relative error, so check percentiles
+        errors.sort_by(f64::total_cmp);
+        assert!(errors[errors.len()/2] < 0.01, "{errors:?}");
+        assert!(errors[errors.len()*99/100] < 0.05, "{errors:?}");
+    }
+}
@@ -1,8 +1,9 @@
 use bevy::{color::Color, math::DVec2};
+use serde::{Deserialize, Serialize};
 
 use crate::G;
 
-use super::{static_generator::StaticGenerator, system_tree::GravitySystemTime, BodyMass, BodyPosition, BodyRadius, BodyVelocity, GravitationalParameter, CALCULATION_TIME_STEP};
+use super::{static_generator::StaticGenerator, system_tree::PhysicalTime, BodyMass, BodyPosition, BodyRadius, BodyVelocity, GravitationalParameter};
 
 
 #[derive(Clone, Debug)]
@@ -41,7 +42,7 @@ impl StaticBody {
         }
     }
 
-    pub fn set_to_time_with_parent_stats(&mut self, time: GravitySystemTime, parent_stats: (BodyPosition, BodyVelocity)) {
+    pub fn set_to_time_with_parent_stats(&mut self, time: PhysicalTime, parent_stats: (BodyPosition, BodyVelocity)) {
         let (relative_position, relative_velocity) = self.static_position.get_position_and_velocity(time);
         self.relative_position = relative_position;
         self.relative_velocity = relative_velocity;
@@ -51,11 +52,11 @@ impl StaticBody {
     }
 
 
-    pub fn initialize_in_system_tree(&mut self, system_depth: usize, parent_generator: &StaticGenerator) {
+    pub fn initialize_in_system_tree(&mut self, system_depth: usize, parent_generator: &StaticGenerator, time: PhysicalTime) {
         self.system_depth = system_depth;
 
         self.parent_generator = parent_generator.clone();
-        let (parent_pos, parent_vel) = parent_generator.get_position_and_velocity(0.);
+        let (parent_pos, parent_vel) = parent_generator.get_position_and_velocity(time);
 
         self.absolute_position = parent_pos + self.relative_position;
         self.absolute_velocity = parent_vel + self.relative_velocity;
@@ -64,6 +65,8 @@ impl StaticBody {
 
     ////////////////////////////// GETTERS //////////////////////////////
     pub fn get_static_position(&self) -> &StaticPosition { &self.static_position }
+    pub fn get_parent_generator(&self) -> &StaticGenerator { &self.parent_generator }
+    pub fn get_system_depth(&self) -> usize { self.system_depth }
     pub fn get_mu(&self) -> GravitationalParameter { self.mu }
     pub fn get_absolute_position(&self) -> BodyPosition { self.absolute_position }
     pub fn get_relative_position(&self) -> BodyPosition { self.relative_position }
@@ -75,7 +78,7 @@ impl StaticBody {
     pub fn get_name(&self) -> String { self.name.clone() }
 
     /// Get center position and radius of orbit
-    pub fn get_orbit_parameters(&self, time: GravitySystemTime) -> (DVec2, f64) {
+    pub fn get_orbit_parameters(&self, time: PhysicalTime) -> (DVec2, f64) {
         match self.static_position {
             StaticPosition::Circular { radius, .. } => {
                 return (self.parent_generator.get_position(time), radius)
@@ -94,7 +97,7 @@ impl StaticBody {
 
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StaticPosition {
     /// A Body that remains motionless relative to the system, staying perfectly in the center
     Still,
@@ -115,6 +118,14 @@ impl StaticPosition {
             Self::Circular { radius, speed, start_angle } => [*radius, (start_angle+speed*time)]
         }
     }
+    /// Circular static position that passes through the position at the provided time with the same angular velocity around the center. \
+    /// Any radial velocity is dropped. A position at the center gives Still.
+    pub fn fit_circular(position: BodyPosition, velocity: BodyVelocity, time: PhysicalTime) -> Self {
+        let radius = position.length();
+        if radius == 0. { return Self::Still }
+        let speed = position.perp_dot(velocity) / radius.powi(2);
+        Self::Circular { radius, speed, start_angle: position.to_angle() - speed*time }
+    }
     pub fn get_radius(&self) -> f64 {
         match self {
             Self::Still => 0.,
@@ -124,26 +135,26 @@ impl StaticPosition {
 
 
     /// Get cartesian coordinates at time t assuming the center of the orbit is (0, 0)
-    pub fn get_position(&self, time: GravitySystemTime) -> BodyPosition {
+    pub fn get_position(&self, time: PhysicalTime) -> BodyPosition {
         match self {
             Self::Still => DVec2::ZERO,
             Self::Circular { radius, speed, start_angle } => {
-                let angle = start_angle+speed*time*CALCULATION_TIME_STEP;
+                let angle = start_angle+speed*time;
                 DVec2 { x: radius*angle.cos(), y: radius*angle.sin() }
             }
         }
     }
-    pub fn get_velocity(&self, time: GravitySystemTime) -> BodyVelocity {
+    pub fn get_velocity(&self, time: PhysicalTime) -> BodyVelocity {
         match self {
             Self::Still => DVec2::ZERO,
-            Self::Circular { radius, speed, start_angle } => DVec2::from_angle(start_angle+speed*time*CALCULATION_TIME_STEP + std::f64::consts::FRAC_PI_2) * (speed * radius)
+            Self::Circular { radius, speed, start_angle } => DVec2::from_angle(start_angle+speed*time + std::f64::consts::FRAC_PI_2) * (speed * radius)
         }
     }
-    pub fn get_position_and_velocity(&self, time: GravitySystemTime) -> (BodyPosition, BodyVelocity) {
+    pub fn get_position_and_velocity(&self, time: PhysicalTime) -> (BodyPosition, BodyVelocity) {
         match self {
             Self::Still => (DVec2::ZERO, DVec2::ZERO),
             Self::Circular { radius, speed, start_angle } => {
-                let angle = start_angle+speed*time*CALCULATION_TIME_STEP;
+                let angle = start_angle+speed*time;
                 (
                     DVec2::new(radius*angle.cos(), radius*angle.sin()),
                     DVec2::from_angle(angle + std::f64::consts::FRAC_PI_2) * (speed * radius)
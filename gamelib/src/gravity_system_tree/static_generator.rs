@@ -2,11 +2,11 @@ use std::collections::VecDeque;
 
 use bevy::math::DVec2;
 
-use super::{static_body::StaticPosition, system_tree::GravitySystemTime, BodyPosition, BodyVelocity};
+use super::{static_body::StaticPosition, system_tree::PhysicalTime, BodyPosition, BodyVelocity};
 
 
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct StaticGenerator {
     chain: VecDeque<StaticPosition>
 }
@@ -15,25 +15,25 @@ impl StaticGenerator {
         Self { chain: VecDeque::new() }
     }
 
-    pub fn get_position(&self, time: GravitySystemTime) -> BodyPosition {
+    pub fn get_position(&self, time: PhysicalTime) -> BodyPosition {
         self.chain
             .iter()
             .fold(DVec2::ZERO, |acc, e| acc + e.get_position(time))
     }
-    pub fn get_last_position(&self, time: GravitySystemTime) -> BodyPosition {
+    pub fn get_last_position(&self, time: PhysicalTime) -> BodyPosition {
         self.chain.back().map(|p| p.get_position(time)).unwrap_or(DVec2::ZERO)
     }
 
-    pub fn get_velocity(&self, time: GravitySystemTime) -> BodyVelocity {
+    pub fn get_velocity(&self, time: PhysicalTime) -> BodyVelocity {
         self.chain
             .iter()
             .fold(DVec2::ZERO, |acc, e| acc + e.get_velocity(time))
     }
-    pub fn get_last_velocity(&self, time: GravitySystemTime) -> BodyVelocity {
+    pub fn get_last_velocity(&self, time: PhysicalTime) -> BodyVelocity {
         self.chain.back().map(|p| p.get_velocity(time)).unwrap_or(DVec2::ZERO)
     }
 
-    pub fn get_position_and_velocity(&self, time: GravitySystemTime) -> (BodyPosition, BodyVelocity) {
+    pub fn get_position_and_velocity(&self, time: PhysicalTime) -> (BodyPosition, BodyVelocity) {
         self.chain
             .iter()
             .fold((DVec2::ZERO, DVec2::ZERO), |mut acc, e| {
@@ -42,7 +42,7 @@ impl StaticGenerator {
                 acc
             })
     }
-    pub fn get_last_position_and_velocity(&self, time: GravitySystemTime) -> (BodyPosition, BodyVelocity) {
+    pub fn get_last_position_and_velocity(&self, time: PhysicalTime) -> (BodyPosition, BodyVelocity) {
         self.chain.back().map(|p| p.get_position_and_velocity(time)).unwrap_or((DVec2::ZERO, DVec2::ZERO))
     }
 
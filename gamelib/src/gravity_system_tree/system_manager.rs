@@ -1,8 +1,11 @@
-use bevy::{ecs::system, prelude::{Commands, Entity, Query, Resource, Visibility}};
+use bevy::{ecs::system, prelude::Resource};
 
-use crate::{pseudo_camera::camera::CameraState, visual_object::VisualObjectData};
+#[cfg(feature = "render")]
+use bevy::prelude::{Query, Visibility};
+#[cfg(feature = "render")]
+use crate::{pseudo_camera::camera::CameraState, visual_object::{BodyEntityMap, VisualObjectData}};
 
-use super::{builder::GravitySystemBuilder, system_tree::{BodyStore, DiscreteGravitySystemTime, GravitySystemTime, GravitySystemTree}};
+use super::{body_id::{BodyId, SystemId}, calendar::{Epoch, TimeParseError}, builder::{GravitySystemBuilder, SystemTreeError}, collision::{find_collisions, CollisionEvent, CollisionHandler, CollisionSettings}, dynamic_body::DynamicBody, frame::{FrameTransform, ReferenceFrame}, static_body::{StaticBody, StaticPosition}, static_generator::StaticGenerator, system_tree::{BodyStore, DiscreteGravitySystemTime, GravitySystemTime, GravitySystemTree, PhysicalTime}, BodyPosition, BodyVelocity, DEFAULT_TICK_LENGTH};
 
 
 
@@ -10,67 +13,509 @@ use super::{builder::GravitySystemBuilder, system_tree::{BodyStore, DiscreteGrav
 pub struct GravitySystemManager {
     system_tree: GravitySystemTree,
 
-    /// Dynamic and static bodies along with their entities if they have them
+    /// Dynamic and static bodies along with their ids
     pub body_store: BodyStore,
 
     /// The time associated with the current position of bodies
-    current_time: DiscreteGravitySystemTime
+    current_time: DiscreteGravitySystemTime,
+
+    /// Physical seconds that pass every tick
+    tick_length: f64,
+
+    /// Calendar used to show physical time as dates
+    epoch: Epoch,
+
+    /// Collisions between dynamic bodies are only checked for when this is set
+    collisions: Option<CollisionHandler>,
+
+    /// Incremented whenever bodies or the tree change other than by stepping, so anything calculated from a copy of the manager can tell it is out of date
+    revision: u64,
 }
 impl GravitySystemManager {
     pub fn new(builder: GravitySystemBuilder) -> Self {
-        let (system_tree, body_store) = builder.build().unwrap();
-        Self { system_tree, body_store, current_time: 0 }
+        Self::try_new(builder).unwrap()
+    }
+    pub fn try_new(builder: GravitySystemBuilder) -> Result<Self, SystemTreeError> {
+        let (system_tree, body_store) = builder.build()?;
+        Ok(Self { system_tree, body_store, current_time: 0, tick_length: DEFAULT_TICK_LENGTH, epoch: Epoch::default(), collisions: None, revision: 0 })
     }
     /// If the new time is greater than the current time, then update dynamic bodies. \
     /// Update visual objects in the query to the new time. \
     /// BE CERTAIN THAT new_time ISNT NEGATIVE OR ELSE UB OCCURS
+    #[cfg(feature = "render")]
     pub fn update_visual_objects(
         &mut self,
         new_time: GravitySystemTime,
+        entity_map: &BodyEntityMap,
         object_query: &mut Query<(&mut VisualObjectData, &mut Visibility)>,
         camera: &CameraState,
     ) {
-        // to_int rounds down, so add 1
-        let new_discrete_time = unsafe { new_time.to_int_unchecked::<DiscreteGravitySystemTime>() + 1 };
-
-        // update dynamic bodies until current_time = new_discrete_time
-        while self.current_time < new_discrete_time {
-            self.current_time += 1;
-            self.body_store.update_dynamic_bodies(&mut self.system_tree, self.current_time);
-        }
+        let new_discrete_time = self.step_to_time(new_time, |_| {});
 
         // Set the position of all static bodies
-        self.body_store.update_static_bodies(&self.system_tree, new_time);
+        self.body_store.update_static_bodies(&self.system_tree, new_time * self.tick_length);
 
         // Set visual objects using the query
         let interpolation_factor = new_time - (new_discrete_time as f64 - 1.);
-        self.system_tree.update_visual_objects(&self.body_store, object_query, camera, true, interpolation_factor)
+        self.system_tree.update_visual_objects(&self.body_store, entity_map, object_query, camera, true, interpolation_factor)
         //self.body_store.update_visual_objects(object_query, interpolation_factor);
     }
 
+    /// Update dynamic bodies until the current time is the tick after new_time, calling on_step after every tick. \
+    /// Returns the new current time. \
+    /// BE CERTAIN THAT new_time ISNT NEGATIVE OR ELSE UB OCCURS
+    pub fn step_to_time(&mut self, new_time: GravitySystemTime, mut on_step: impl FnMut(&Self)) -> DiscreteGravitySystemTime {
+        // to_int rounds down, so add 1
+        let new_discrete_time = unsafe { new_time.to_int_unchecked::<DiscreteGravitySystemTime>() + 1 };
+
+        // update dynamic bodies until current_time = new_discrete_time
+        while self.current_time < new_discrete_time {
+            self.step();
+            on_step(self);
+        }
+        new_discrete_time
+    }
+
     pub fn step(&mut self) {
         self.current_time += 1;
-        self.body_store.update_dynamic_bodies(&mut self.system_tree, self.current_time);
+        self.body_store.update_dynamic_bodies(&mut self.system_tree, self.current_time, self.tick_length);
+        if self.collisions.is_some() {
+            self.resolve_collisions();
+        }
+    }
+
+    /// Set how many physical seconds pass every tick. Shorter ticks are more accurate but need more ticks to cover the same time. \
+    /// Rails are placed using the physical time, so this should be set before the manager is stepped.
+    pub fn with_tick_length(mut self, tick_length: f64) -> Self {
+        self.tick_length = tick_length;
+        self
     }
 
-    /// Populate the body store with entities
-    pub fn spawn_bodies(&mut self, commands: &mut Commands) {
-        self.body_store.spawn_visual_objects(commands);
+    /// Set the date that time zero corresponds to
+    pub fn with_epoch(mut self, epoch: Epoch) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Resolve collisions between dynamic bodies after every tick, replacing the bodies that collide with merged bodies or fragments. \
+    /// Pass None to let bodies pass through each other, which is the default.
+    pub fn set_collision_settings(&mut self, settings: Option<CollisionSettings>) {
+        self.collisions = settings.map(CollisionHandler::new);
+        self.revision += 1;
+    }
+    pub fn with_collision_settings(mut self, settings: Option<CollisionSettings>) -> Self {
+        self.set_collision_settings(settings);
+        self
+    }
+    pub fn get_collision_settings(&self) -> Option<&CollisionSettings> {
+        self.collisions.as_ref().map(|c| &c.settings)
+    }
+    /// Take every collision resolved since the last call. Used to spawn and despawn visual objects for the bodies involved.
+    pub fn take_collision_events(&mut self) -> Vec<CollisionEvent> {
+        self.collisions.as_mut().map_or(vec![], |c| std::mem::take(&mut c.events))
+    }
+
+    fn resolve_collisions(&mut self) {
+        let time = self.get_physical_time();
+        let Some(handler) = &mut self.collisions else { return };
+        let pairs = find_collisions(&self.system_tree, &self.body_store.dynamic_bodies);
+        if pairs.is_empty() { return }
+        self.revision += 1;
+        for (system, a, b) in pairs.iter().cloned() {
+            let store = &self.body_store;
+            let (body_a, body_b) = (store.dynamic_bodies.at(a), store.dynamic_bodies.at(b));
+            let (kind, impact_speed, new_bodies) = handler.resolve(body_a, body_b);
+            let (system_depth, parent_generator) = (body_a.get_system_depth(), body_a.get_parent_generator().clone());
+            let removed = [store.dynamic_ids[a], store.dynamic_ids[b]];
+
+            let created = new_bodies.into_iter().map(|mut body| {
+                body.initialize_in_system_tree(system_depth, &parent_generator, time);
+                let index = self.body_store.add_dynamic_body_to_store(body);
+                self.system_tree.add_dynamic_index(system, index);
+                self.body_store.dynamic_ids[index]
+            }).collect();
+            handler.events.push(CollisionEvent { time: self.current_time, kind, impact_speed, removed, created });
+        }
+        // Remove collided bodies last so the indices of the pairs stay valid until every pair is resolved
+        let mut removed = pairs.into_iter().flat_map(|(_, a, b)| [a, b]).collect::<Vec<_>>();
+        removed.sort_unstable();
+        for index in removed.into_iter().rev() {
+            self.system_tree.remove_dynamic_index(index);
+            if let Some(from) = self.body_store.swap_remove_dynamic_body(index) {
+                self.system_tree.remap_dynamic_index(from, index);
+            }
+        }
     }
 
     pub fn get_current_time(&self) -> DiscreteGravitySystemTime {
         self.current_time
     }
+    pub fn get_tick_length(&self) -> f64 {
+        self.tick_length
+    }
+    /// Seconds that have passed in the simulation
+    pub fn get_physical_time(&self) -> PhysicalTime {
+        self.current_time as PhysicalTime * self.tick_length
+    }
+    pub fn get_revision(&self) -> u64 {
+        self.revision
+    }
+    pub fn get_epoch(&self) -> &Epoch {
+        &self.epoch
+    }
+    /// Date of a possibly fractional tick in the calendar of the epoch
+    pub fn get_date(&self, time: GravitySystemTime) -> String {
+        self.epoch.format_date(time * self.tick_length)
+    }
+    /// Tick of a date, a duration since the epoch such as "3d 4h", or a duration from the current time such as "+1h". \
+    /// Used to schedule anything that happens at a tick, like burns and time warps.
+    pub fn parse_tick(&self, input: &str) -> Result<GravitySystemTime, TimeParseError> {
+        self.epoch.parse_time(input, self.get_physical_time()).map(|time| time / self.tick_length)
+    }
+
+    pub fn get_system(&self, id: SystemId) -> Option<&GravitySystemTree> {
+        self.system_tree.find_system(id)
+    }
+
+    /// Get the system that the body is directly in
+    pub fn get_body_system(&self, id: BodyId) -> Option<&GravitySystemTree> {
+        if let Some(index) = self.body_store.get_dynamic_index(id) {
+            return self.system_tree.find_dynamic_body_system(index)
+        }
+        let index = self.body_store.get_static_index(id)?;
+        self.system_tree.find_static_body_system(index)
+    }
+    pub fn get_body_system_id(&self, id: BodyId) -> Option<SystemId> {
+        self.get_body_system(id).map(|s| s.id)
+    }
+    /// Name of the body at the center of the system that the body is directly in
+    pub fn get_body_system_name(&self, id: BodyId) -> Option<String> {
+        let center = *self.get_body_system(id)?.static_body_indices.first()?;
+        Some(self.body_store.static_bodies[center].get_name())
+    }
+    /// Position of the system a dynamic or static body is in, and the body's position relative to it at the current tick
+    pub fn get_body_relative_position(&self, id: BodyId) -> Option<(&StaticGenerator, BodyPosition)> {
+        if let Some(index) = self.body_store.get_dynamic_index(id) {
+            let body = self.body_store.dynamic_bodies.at(index);
+            return Some((body.get_parent_generator(), body.get_current_relative_position()))
+        }
+        let body = &self.body_store.static_bodies[self.body_store.get_static_index(id)?];
+        Some((body.get_parent_generator(), body.get_static_position().get_position(self.get_physical_time())))
+    }
+    /// Id, depth, center position and radius of every system at a time, depth first. The root system has depth 0.
+    pub fn get_system_bounds(&self, time: PhysicalTime) -> Vec<(SystemId, usize, BodyPosition, f64)> {
+        let mut bounds = Vec::new();
+        let mut stack = vec![(&self.system_tree, 0)];
+        while let Some((system, depth)) = stack.pop() {
+            let center = system.parent_generator.get_position(time) + system.position.get_position(time);
+            bounds.push((system.id, depth, center, system.radius));
+            stack.extend(system.child_systems.iter().rev().map(|s| (s, depth + 1)));
+        }
+        bounds
+    }
+    /// Id of every system with the name of the body at its center, depth first. Systems without a center body are left out.
+    pub fn get_system_names(&self) -> Vec<(SystemId, String)> {
+        let mut names = Vec::new();
+        let mut stack = vec![&self.system_tree];
+        while let Some(system) = stack.pop() {
+            if let Some(center) = system.static_body_indices.first() {
+                names.push((system.id, self.body_store.static_bodies[*center].get_name()));
+            }
+            stack.extend(system.child_systems.iter().rev());
+        }
+        names
+    }
+
+    /// Origin and rotation of a reference frame over time. \
+    /// Gives None for the local frame and for frames of systems or static bodies that don't exist.
+    pub fn get_frame_transform(&self, frame: ReferenceFrame) -> Option<FrameTransform> {
+        match frame {
+            ReferenceFrame::Local => None,
+            ReferenceFrame::Inertial => Some(FrameTransform::default()),
+            ReferenceFrame::System(id) => {
+                let system = self.get_system(id)?;
+                let mut origin = system.parent_generator.clone();
+                origin.push_end(system.position.clone());
+                Some(FrameTransform::new(origin, None))
+            },
+            ReferenceFrame::CoRotating(id) => {
+                let body = &self.body_store.static_bodies[self.body_store.get_static_index(id)?];
+                let mut origin = body.get_parent_generator().clone();
+                // The body at the center of a system turns with the orbit of the system
+                let rotation = match body.get_static_position() {
+                    StaticPosition::Still => origin.pop_end(),
+                    orbit => orbit.clone(),
+                };
+                Some(FrameTransform::new(origin, Some(rotation)))
+            },
+        }
+    }
+
+    /// Turn a static body into a dynamic body with the same position, velocity and id, for example when a moon is knocked out of its orbit. \
+    /// The body stays in the system that held it and pulls on the other dynamic bodies there through mutual gravity. \
+    /// Since the id does not change, visual objects stay attached to the body. \
+    /// The body at the center of a system can't be released, since the system would be left on its rails without its center.
+    pub fn promote_static_body(&mut self, id: BodyId) -> Result<(), BodyConversionError> {
+        let static_index = self.body_store.get_static_index(id).ok_or(BodyConversionError::NoStaticBody)?;
+        let system = self.system_tree.find_static_body_system(static_index).ok_or(BodyConversionError::NoStaticBody)?;
+        if system.static_body_indices.first() == Some(&static_index) {
+            return Err(BodyConversionError::SystemCenter)
+        }
+        let time = self.get_physical_time();
+        let mu = self.body_store.static_bodies[static_index].get_mu();
+        self.system_tree.replace_static_with_dynamic(static_index, self.body_store.dynamic_bodies.len(), mu);
+        let (static_body, moved) = self.body_store.swap_remove_static_body(static_index);
+        if let Some(from) = moved {
+            self.system_tree.remap_static_index(from, static_index);
+        }
+
+        let (position, velocity) = static_body.get_static_position().get_position_and_velocity(time);
+        let mut body = DynamicBody::new(position, velocity, static_body.get_mass(), static_body.get_radius(), static_body.get_color(), static_body.get_name())
+            .with_mutual_gravity(true);
+        body.initialize_in_system_tree(static_body.get_system_depth(), static_body.get_parent_generator(), time);
+        self.body_store.add_dynamic_body_with_id(body, id);
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Put a dynamic body on rails so it no longer costs anything to update. \
+    /// Its current orbit is fit to a circular static position that keeps its distance from the system center and its angular velocity, so any radial velocity is lost. \
+    /// The body becomes a static body of the system that held it, with the same id, and its mass is added to the mu of the system and its parents. \
+    /// A system without static bodies has no center to fit the orbit around, so its bodies can't be demoted.
+    pub fn demote_dynamic_body(&mut self, id: BodyId) -> Result<(), BodyConversionError> {
+        let dynamic_index = self.body_store.get_dynamic_index(id).ok_or(BodyConversionError::NoDynamicBody)?;
+        let system = self.system_tree.find_dynamic_body_system(dynamic_index).ok_or(BodyConversionError::NoDynamicBody)?;
+        if system.static_body_indices.is_empty() {
+            return Err(BodyConversionError::NoSystemCenter)
+        }
+        let time = self.get_physical_time();
+        let body = self.body_store.dynamic_bodies.at(dynamic_index);
+        let static_position = StaticPosition::fit_circular(body.get_current_relative_position(), body.get_current_relative_velocity(), time);
+        let mut static_body = StaticBody::new(static_position, body.get_mass(), body.get_radius(), body.get_color(), body.get_name());
+        let parent_generator = body.get_parent_generator().clone();
+        static_body.initialize_in_system_tree(body.get_system_depth(), &parent_generator, time);
+        static_body.set_to_time_with_parent_stats(time, parent_generator.get_position_and_velocity(time));
+
+        self.system_tree.replace_dynamic_with_static(dynamic_index, self.body_store.static_bodies.len(), static_body.get_mu());
+        self.body_store.add_static_body_with_id(static_body, id);
+        if let Some(from) = self.body_store.swap_remove_dynamic_body(dynamic_index) {
+            self.system_tree.remap_dynamic_index(from, dynamic_index);
+        }
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Move a dynamic body to a new absolute position and velocity, for example when it is edited by hand. \
+    /// The body stays in its current system until the next tick moves it into the system it is now in. \
+    /// Returns false if there is no dynamic body with the id.
+    pub fn set_dynamic_body_state(&mut self, id: BodyId, position: BodyPosition, velocity: BodyVelocity) -> bool {
+        let Some(index) = self.body_store.get_dynamic_index(id) else { return false };
+        let (parent_pos, parent_vel) = self.body_store.dynamic_bodies.at(index).get_parent_generator().get_position_and_velocity(self.get_physical_time());
+        self.body_store.dynamic_bodies.set_state(index, position - parent_pos, velocity - parent_vel, parent_pos, parent_vel);
+        self.revision += 1;
+        true
+    }
+
+    /// Get the static body that a dynamic body is touching. \
+    /// Only static bodies in the body's own system are checked, which includes the body the system is centered on.
+    pub fn find_static_impact(&self, id: BodyId) -> Option<BodyId> {
+        let index = self.body_store.get_dynamic_index(id)?;
+        let body = self.body_store.dynamic_bodies.at(index);
+        let system = self.system_tree.find_dynamic_body_system(index)?;
+        let time = self.get_physical_time();
+        system.static_body_indices
+            .iter()
+            .find(|i| {
+                let static_body = &self.body_store.static_bodies[**i];
+                let offset = body.get_current_relative_position() - static_body.get_static_position().get_position(time);
+                offset.length_squared() < (static_body.get_radius() + body.get_radius()).powi(2)
+            })
+            .map(|i| self.body_store.static_ids[*i])
+    }
 
     /// Copy the system and retain one dynamic body
-    pub fn retain_clone(&self, entity: Entity) -> Option<Self> {
-        let Some((body_store, idx)) = self.body_store.retain_clone(entity) else { return None };
-        let system_tree = self.system_tree.retain_clone(idx);
-        if system_tree.total_child_dynamic_bodies == 0 { return None };
+    pub fn retain_clone(&self, id: BodyId) -> Option<Self> {
+        self.retain_clone_many(&[id])
+    }
+    /// Copy the system and retain the provided dynamic bodies, which keep the order of the ids. \
+    /// None if any of the ids isn't a dynamic body.
+    pub fn retain_clone_many(&self, ids: &[BodyId]) -> Option<Self> {
+        let (body_store, indices) = self.body_store.retain_clone_many(ids)?;
+        let system_tree = self.system_tree.retain_clone_many(&indices);
+        if system_tree.total_child_dynamic_bodies != ids.len() { return None };
         Some(Self {
             system_tree,
             body_store,
-            current_time: self.current_time
+            current_time: self.current_time,
+            tick_length: self.tick_length,
+            epoch: self.epoch.clone(),
+            collisions: None,
+            revision: self.revision,
         })
     }
-}
\ No newline at end of file
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyConversionError {
+    /// No static body has the provided id
+    NoStaticBody,
+    /// No dynamic body has the provided id
+    NoDynamicBody,
+    /// The static body is the center of a system
+    SystemCenter,
+    /// The dynamic body is in a system without a static body at its center
+    NoSystemCenter,
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use bevy::{color::Color, math::DVec2};
+    use crate::{gravity_system_tree::static_body::StaticPosition, math::get_orbital_speed};
+    use super::*;
+
+    #[test]
+    fn promote_and_demote_keep_state_and_mu() {
+        let star_mass = 1e22;
+        let planet_mass = 1e18;
+        let planet_orbit = StaticPosition::Circular { radius: 50_000., speed: get_orbital_speed(star_mass, 50_000.), start_angle: 0. };
+        let bodies = (1..=3).map(|i| {
+            let radius = 10_000. * i as f64;
+            DynamicBody::new(DVec2::X * radius, DVec2::Y * get_orbital_speed(star_mass, radius) * radius, 1., 1., Color::WHITE, format!("{i}"))
+        }).collect::<Vec<_>>();
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_static_bodies(&[
+                StaticBody::new(StaticPosition::Still, star_mass, 1., Color::WHITE, "star".into()),
+                StaticBody::new(planet_orbit.clone(), planet_mass, 1., Color::WHITE, "planet".into()),
+            ])
+            .with_dynamic_bodies(&bodies);
+        let mut manager = GravitySystemManager::new(system);
+        for _ in 0..1000 {
+            manager.step();
+        }
+        let root = SystemId(0);
+        let initial_mu = manager.get_system(root).unwrap().mu;
+        let star = manager.body_store.static_ids[0];
+        assert_eq!(manager.promote_static_body(star), Err(BodyConversionError::SystemCenter));
+        assert_eq!(manager.get_revision(), 0);
+        let planet = manager.body_store.static_ids[1];
+        let planet_position = planet_orbit.get_position(manager.get_physical_time());
+
+        manager.promote_static_body(planet).unwrap();
+        let body = manager.body_store.get_dynamic_body(planet).unwrap();
+        assert_eq!(body.get_current_absolute_position(), planet_position);
+        assert!(body.get_mutual_gravity());
+        assert!(manager.body_store.get_static_index(planet).is_none());
+        assert_eq!(manager.get_body_system_id(planet), Some(root));
+        assert!((manager.get_system(root).unwrap().mu - (initial_mu - planet_mass * crate::G)).abs() < initial_mu * 1e-12);
+        assert_eq!(manager.promote_static_body(planet), Err(BodyConversionError::NoStaticBody));
+
+        // Demoting the first body moves the last dynamic body into its index
+        let first = manager.body_store.dynamic_ids[0];
+        let first_position = manager.body_store.get_dynamic_body(first).unwrap().get_current_absolute_position();
+        let others = manager.body_store.dynamic_ids[1..].iter().map(|id| (*id, manager.body_store.get_dynamic_body(*id).unwrap().get_current_absolute_position())).collect::<Vec<_>>();
+        manager.demote_dynamic_body(first).unwrap();
+        manager.body_store.update_static_bodies(&manager.system_tree, manager.get_physical_time());
+        let first_body = manager.body_store.get_static_body(first).unwrap();
+        assert!(first_body.get_absolute_position().distance(first_position) < 1e-6);
+        assert_eq!(manager.get_body_system_id(first), Some(root));
+        for (id, position) in others {
+            assert_eq!(manager.body_store.get_dynamic_body(id).unwrap().get_current_absolute_position(), position);
+            assert_eq!(manager.get_body_system_id(id), Some(root));
+        }
+        let system = manager.get_system(root).unwrap();
+        assert_eq!(system.total_child_dynamic_bodies, manager.body_store.dynamic_bodies.len());
+        assert!((system.mu - (initial_mu - planet_mass * crate::G + crate::G)).abs() < initial_mu * 1e-12);
+
+        // The rail keeps the distance the body had when it was demoted
+        for _ in 0..1000 {
+            manager.step();
+        }
+        let time = manager.get_physical_time();
+        manager.body_store.update_static_bodies(&manager.system_tree, time);
+        let radius = manager.body_store.get_static_body(first).unwrap().get_absolute_position().length();
+        assert!((radius - first_position.length()).abs() < 1e-6, "{radius}");
+
+        // A body in a system without static bodies would become its center and could never be promoted again
+        let mut empty = GravitySystemManager::new(GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_dynamic_bodies(&bodies));
+        let drifter = empty.body_store.dynamic_ids[0];
+        assert_eq!(empty.demote_dynamic_body(drifter), Err(BodyConversionError::NoSystemCenter));
+        assert!(empty.body_store.get_dynamic_body(drifter).is_some());
+        assert_eq!(empty.get_revision(), 0);
+    }
+
+    #[test]
+    fn longer_ticks_cover_the_same_time_in_fewer_steps() {
+        let star_mass = 1e22;
+        let moon_orbit = StaticPosition::Circular { radius: 50_000., speed: get_orbital_speed(star_mass, 50_000.), start_angle: 0. };
+        let radius = 10_000.;
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_static_bodies(&[
+                StaticBody::new(StaticPosition::Still, star_mass, 1., Color::WHITE, "star".into()),
+                StaticBody::new(moon_orbit.clone(), 1., 1., Color::WHITE, "moon".into()),
+            ])
+            .with_dynamic_bodies(&[DynamicBody::new(DVec2::X * radius, DVec2::Y * get_orbital_speed(star_mass, radius) * radius, 1., 1., Color::WHITE, "".into())]);
+        let mut fine = GravitySystemManager::new(system.clone());
+        let mut coarse = GravitySystemManager::new(system).with_tick_length(DEFAULT_TICK_LENGTH * 10.);
+        for _ in 0..10_000 {
+            fine.step();
+        }
+        for _ in 0..1000 {
+            coarse.step();
+        }
+        assert_eq!(coarse.get_current_time(), 1000);
+        assert!((fine.get_physical_time() - coarse.get_physical_time()).abs() < 1e-12);
+
+        // Rails are placed by physical time and dynamic bodies end up in the same place along their orbit
+        for manager in [&mut fine, &mut coarse] {
+            let time = manager.get_physical_time();
+            manager.body_store.update_static_bodies(&manager.system_tree, time);
+        }
+        let moon = |m: &GravitySystemManager| m.body_store.static_bodies[1].get_absolute_position();
+        assert!(moon(&fine).distance(moon(&coarse)) < 1e-6);
+        let body = |m: &GravitySystemManager| m.body_store.dynamic_bodies.at(0).get_current_absolute_position();
+        assert!(body(&fine).distance(body(&coarse)) < radius * 1e-2, "{}", body(&fine).distance(body(&coarse)));
+        assert!(body(&fine).distance(DVec2::X * radius) > radius * 0.1);
+    }
+
+    #[test]
+    fn system_bounds_are_depth_first_with_rail_centers() {
+        let planet_orbit = StaticPosition::Circular { radius: 50_000., speed: 0.01, start_angle: 0. };
+        let moon_orbit = StaticPosition::Circular { radius: 2_000., speed: 0.3, start_angle: 1. };
+        let system = |position: StaticPosition, radius: f64, name: &str| GravitySystemBuilder::new()
+            .with_position(position)
+            .with_radius(radius)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, 1e18, 1., Color::WHITE, name.into())]);
+        let moon = system(moon_orbit.clone(), 300., "moon");
+        let planet = system(planet_orbit.clone(), 5_000., "planet").with_children(&[moon]);
+        let other = system(StaticPosition::Circular { radius: 90_000., speed: 0.005, start_angle: 2. }, 4_000., "other");
+        let manager = GravitySystemManager::new(system(StaticPosition::Still, 1e9, "star").with_children(&[planet, other]));
+
+        let time = 12.5;
+        let bounds = manager.get_system_bounds(time);
+        let names = manager.get_system_names();
+        assert_eq!(bounds.iter().map(|(id, ..)| *id).collect::<Vec<_>>(), names.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+        assert_eq!(names.iter().map(|(_, n)| n.as_str()).collect::<Vec<_>>(), ["star", "planet", "moon", "other"]);
+        assert_eq!(bounds.iter().map(|(_, depth, ..)| *depth).collect::<Vec<_>>(), [0, 1, 2, 1]);
+        assert_eq!(bounds.iter().map(|(.., radius)| *radius).collect::<Vec<_>>(), [1e9, 5_000., 300., 4_000.]);
+
+        let (_, _, moon_center, _) = bounds[2];
+        assert_eq!(bounds[0].2, DVec2::ZERO);
+        assert!(bounds[1].2.distance(planet_orbit.get_position(time)) < 1e-9);
+        assert!(moon_center.distance(planet_orbit.get_position(time) + moon_orbit.get_position(time)) < 1e-9);
+    }
+}
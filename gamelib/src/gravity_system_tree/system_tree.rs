@@ -1,20 +1,83 @@
 use core::f64;
-use std::collections::VecDeque;
-
-use bevy::{color::Color, math::DVec2, prelude::{Commands, Entity, Query, Visibility}};
+use bevy::{color::Color, math::DVec2, utils::HashMap};
 use itertools::Itertools;
+use rayon::prelude::*;
 
-use crate::{pseudo_camera::camera::CameraState, visual_object::{VisualObjectBundle, VisualObjectData}};
+#[cfg(feature = "render")]
+use bevy::prelude::{Query, Visibility};
+#[cfg(feature = "render")]
+use crate::{pseudo_camera::camera::CameraState, visual_object::{BodyEntityMap, VisualObjectData}};
 
-use super::{builder::GravitySystemBuilder, dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}, static_generator::StaticGenerator, BodyPosition, BodyVelocity};
+use super::{body_id::{BodyId, SystemId}, child_grid::ChildSystemGrid, solver::{BarnesHutTree, GravitySolver}, builder::GravitySystemBuilder, dynamic_body::{DynamicBodies, DynamicBodiesPtr, DynamicBody, DynamicBodyRef}, static_body::{StaticBody, StaticPosition}, static_generator::StaticGenerator, BodyPosition, BodyVelocity};
 
 pub type DiscreteGravitySystemTime = u64;
+/// Time in ticks, with a fractional part for positions between ticks
 pub type GravitySystemTime = f64;
+/// Time in seconds, used to place static bodies and systems on their rails
+pub type PhysicalTime = f64;
+
+/// Systems with at least this many dynamic bodies update their bodies in parallel
+const PARALLEL_BODY_THRESHOLD: usize = 512;
+/// Number of bodies given to each thread when calculating gravity in parallel
+const PARALLEL_GRAVITY_CHUNK: usize = 128;
+/// Systems with at least this many dynamic bodies under them update their child systems in parallel
+const PARALLEL_SUBTREE_THRESHOLD: usize = 256;
+/// Systems smaller than this on screen only show their center body
+pub const MIN_VISIBLE_SYSTEM_RADIUS: f64 = 2.5;
+
+/// Opacity of something with this radius on screen, rising from 0 at MIN_VISIBLE_SYSTEM_RADIUS to 1 at fade_range times that radius
+pub fn get_zoom_fade(screen_radius: f64, fade_range: f64) -> f64 {
+    ((screen_radius - MIN_VISIBLE_SYSTEM_RADIUS) / (MIN_VISIBLE_SYSTEM_RADIUS * (fade_range - 1.))).clamp(0., 1.)
+}
+
+
+
+/// Run f for every index, in parallel if there are enough indices to be worth it. \
+/// Each call must only touch the body at its own index so the result does not depend on the order.
+#[inline]
+fn for_each_body_index(indices: &[usize], f: impl Fn(usize) + Send + Sync) {
+    if indices.len() >= PARALLEL_BODY_THRESHOLD {
+        indices.par_iter().for_each(|index| f(*index));
+    } else {
+        indices.iter().for_each(|index| f(*index));
+    }
+}
+/// Same as for_each_body_index, but f is given chunks of indices
+#[inline]
+fn for_each_body_chunk(indices: &[usize], f: impl Fn(&[usize]) + Send + Sync) {
+    if indices.len() >= PARALLEL_BODY_THRESHOLD {
+        indices.par_chunks(PARALLEL_GRAVITY_CHUNK).for_each(f);
+    } else {
+        f(indices);
+    }
+}
+
+
+/// The tick being calculated and where the system that is being updated is
+#[derive(Clone, Copy)]
+pub(super) struct TickContext {
+    pub new_time: DiscreteGravitySystemTime,
+    pub tick_length: f64,
+    /// Absolute position and velocity of the system center at the new time
+    pub parent_pos: BodyPosition,
+    pub parent_vel: BodyVelocity,
+}
+impl TickContext {
+    /// Context of a child system centered at an offset from this system's center
+    fn with_offset(self, position: BodyPosition, velocity: BodyVelocity) -> Self {
+        Self { parent_pos: self.parent_pos + position, parent_vel: self.parent_vel + velocity, ..self }
+    }
 
+    fn get_new_physical_time(&self) -> PhysicalTime {
+        self.new_time as PhysicalTime * self.tick_length
+    }
+}
 
 
 #[derive(Clone)]
 pub struct GravitySystemTree {
+    /// Stable handle used to refer to this system from outside the tree
+    pub id: SystemId,
     /// Index into dynamic body array where the body can be found
     pub dynamic_body_indices: Vec<usize>,
     /// Lone bodies in the system. \
@@ -24,6 +87,9 @@ pub struct GravitySystemTree {
     /// Child system masses come first then static bodies \
     /// Used to reduce allocations and the number of times the static position of systems and bodies is calculated each iteration
     pub static_masses: Vec<(DVec2, f64)>, 
+    /// Index, position and gravitational parameter of dynamic bodies in the system that have mutual gravity. \
+    /// Refreshed every time gravity is calculated, and empty for systems that only hold massless bodies.
+    pub dynamic_masses: Vec<(usize, DVec2, f64)>,
     /// Gravitational acceleration will only be updated if new_time % time_step == 0
     pub time_step: u64,
     /// The size of the entire system. \
@@ -33,51 +99,128 @@ pub struct GravitySystemTree {
     pub position: StaticPosition,
     /// Used to calculate the position of the parent system at a point in time. Use sparingly
     pub parent_generator: StaticGenerator,
-    /// Total gravitational parameter of all static bodies in the system, including bodies in child systems. Mass of dynamic bodies is negligible.
+    /// Total gravitational parameter of all static bodies in the system, including bodies in child systems. Dynamic bodies are not included, even ones with mutual gravity.
     pub mu: f64,
     /// Total number of dynamic bodies that exist under this system. \
     pub total_child_dynamic_bodies: usize,
+    /// Used to find which child system a body is entering
+    pub child_grid: ChildSystemGrid,
+    /// How dynamic bodies with mutual gravity pull on each other
+    pub solver: GravitySolver,
+    /// Only used by the Barnes-Hut solver
+    pub barnes_hut: BarnesHutTree,
 }
 impl GravitySystemTree {
     fn calculate_gravity(
         &mut self,
-        current_time: GravitySystemTime,
-        static_body_vec: &Vec<StaticBody>,
-        dynamic_body_vec: &mut Vec<DynamicBody>,
+        current_time: PhysicalTime,
+        static_body_vec: &[StaticBody],
+        dynamic_bodies: DynamicBodiesPtr,
     ) {
         self.update_static_masses(static_body_vec, current_time);
-        for index in self.dynamic_body_indices.iter().cloned() {
-            let body = unsafe { dynamic_body_vec.get_unchecked_mut(index) };
-            body.calculate_gravitational_acceleration(&self.static_masses);
+        let static_masses = &self.static_masses;
+        for_each_body_chunk(&self.dynamic_body_indices, |indices| unsafe {
+            dynamic_bodies.calculate_gravitational_acceleration(indices, static_masses)
+        });
+
+        self.update_dynamic_masses(dynamic_bodies);
+        if self.dynamic_masses.is_empty() { return }
+        match self.solver {
+            GravitySolver::Direct => {
+                let dynamic_masses = &self.dynamic_masses;
+                for_each_body_index(&self.dynamic_body_indices, |index| unsafe {
+                    dynamic_bodies.add_mutual_gravitational_acceleration(index, dynamic_masses)
+                });
+            }
+            GravitySolver::BarnesHut { opening_angle } => {
+                self.barnes_hut.rebuild(&self.dynamic_masses);
+                let tree = &self.barnes_hut;
+                for_each_body_index(&self.dynamic_body_indices, |index| unsafe {
+                    dynamic_bodies.add_tree_gravitational_acceleration(index, tree, opening_angle)
+                });
+            }
         }
     }
 
-    pub fn move_dynamic_bodies(&mut self, new_time: DiscreteGravitySystemTime, body_vec: &mut Vec<DynamicBody>, should_accelerate: bool, parent_pos: BodyPosition, parent_vel: BodyVelocity) {
-        for index in self.dynamic_body_indices.iter().cloned() {
-            let body = unsafe { body_vec.get_unchecked_mut(index) };
-            body.accelerate_and_move_body(new_time, should_accelerate, parent_pos, parent_vel, self.time_step as f64)
+    fn move_dynamic_bodies(&mut self, tick: TickContext, dynamic_bodies: DynamicBodiesPtr, should_accelerate: bool) {
+        let time_step = self.time_step as f64;
+        for_each_body_index(&self.dynamic_body_indices, |index| unsafe {
+            dynamic_bodies.accelerate_and_move_body(index, &tick, should_accelerate, time_step)
+        });
+    }
+
+    /// Performs one time step of gravity calculation for this system and its children. \
+    /// Sibling systems own disjoint bodies so large subtrees are updated in parallel. \
+    /// Child elevators are always merged in child order, so the result is identical to updating serially.
+    fn update_dynamic_bodies_recursive(
+        &mut self,
+        static_bodies: &[StaticBody],
+        dynamic_bodies: DynamicBodiesPtr,
+        tick: TickContext,
+        elevator: &mut Vec<usize>,
+    ) {
+        let new_ftime = tick.get_new_physical_time();
+        if self.dynamic_body_indices.len() != 0 {
+            let should_accelerate = tick.new_time % self.time_step == 0;
+            if should_accelerate {
+                self.calculate_gravity((tick.new_time-1) as PhysicalTime * tick.tick_length, static_bodies, dynamic_bodies);
+            }
+            self.move_dynamic_bodies(tick, dynamic_bodies, should_accelerate);
+        } 
+        
+        let update_child = |child_system: &mut GravitySystemTree, child_elevator: &mut Vec<usize>| {
+            if child_system.total_child_dynamic_bodies < 1 { return }
+            let (child_pos, child_vel) = child_system.position.get_position_and_velocity(new_ftime); // Should current time or new time be used here? I think new time since its used to set absolute position of dynamic bodies
+            child_system.update_dynamic_bodies_recursive(
+                static_bodies,
+                dynamic_bodies,
+                tick.with_offset(child_pos, child_vel),
+                child_elevator
+            )
+        };
+        if self.total_child_dynamic_bodies.saturating_sub(self.dynamic_body_indices.len()) >= PARALLEL_SUBTREE_THRESHOLD {
+            let child_elevators = self.child_systems
+                .par_iter_mut()
+                .map(|child_system| {
+                    let mut child_elevator = vec![];
+                    update_child(child_system, &mut child_elevator);
+                    child_elevator
+                })
+                .collect::<Vec<_>>();
+            for child_elevator in child_elevators {
+                self.dynamic_body_indices.extend_from_slice(&child_elevator);
+            }
+        } else {
+            let mut child_elevator = vec![];
+            for child_system in &mut self.child_systems {
+                update_child(child_system, &mut child_elevator);
+            }
+            self.dynamic_body_indices.extend_from_slice(&child_elevator);
         }
+
+        self.ascend_or_descend_bodies(new_ftime, dynamic_bodies, elevator);
     }
 
-    fn ascend_or_descend_bodies(&mut self, new_time: GravitySystemTime, bodies_vec: &mut Vec<DynamicBody>, elevator: &mut Vec<usize>) {
+    fn ascend_or_descend_bodies(&mut self, new_time: PhysicalTime, dynamic_bodies: DynamicBodiesPtr, elevator: &mut Vec<usize>) {
         let mut remove_list = vec![];
+        if !self.dynamic_body_indices.is_empty() {
+            self.child_grid.rebuild(&self.child_systems, new_time);
+        }
 
         for (index, body_index) in self.dynamic_body_indices.iter().cloned().enumerate() {
-            let body_mut = unsafe { bodies_vec.get_unchecked_mut(body_index) };
-            if body_mut.relative_magnitude_squared() > self.radius.powi(2) {
-                body_mut.translate_to_parent(new_time);
+            if unsafe { dynamic_bodies.relative_magnitude_squared(body_index) } > self.radius.powi(2) {
+                unsafe { dynamic_bodies.translate_to_parent(body_index, new_time) };
                 elevator.push(body_index);
                 remove_list.push(index);
                 self.total_child_dynamic_bodies -= 1;
                 continue;
             }
-            for child_system in &mut self.child_systems {
-                let system_position = child_system.position.get_position(new_time);
-                if body_mut.distance_squared(system_position) > child_system.radius.powi(2) { continue }
-                body_mut.translate_to_child(new_time, &child_system.position);
+            let position = unsafe { dynamic_bodies.get_relative_position(body_index) };
+            if let Some(child_index) = self.child_grid.find_containing(position) {
+                let child_system = &mut self.child_systems[child_index];
+                unsafe { dynamic_bodies.translate_to_child(body_index, new_time, &child_system.position) };
                 child_system.insert_body_index(body_index);
                 remove_list.push(index);
-                break;
             }
         }
         // remove list is guaranteed to be in order, so iterate in reverse to avoid problems with swap_remove
@@ -91,9 +234,20 @@ impl GravitySystemTree {
         self.dynamic_body_indices.push(index);
     }
 
+    /// Clear then populate the dynamic_masses vec with the current position of dynamic bodies that have mutual gravity
+    #[inline]
+    fn update_dynamic_masses(&mut self, dynamic_bodies: DynamicBodiesPtr) {
+        self.dynamic_masses.clear();
+        for index in self.dynamic_body_indices.iter().cloned() {
+            if let Some((position, mu)) = unsafe { dynamic_bodies.get_mutual_mass(index) } {
+                self.dynamic_masses.push((index, position, mu));
+            }
+        }
+    }
+
     /// Clear then populate the static_masses vec of the system using the provided time
     #[inline]
-    fn update_static_masses(&mut self, body_vec: &Vec<StaticBody>, time: GravitySystemTime) {
+    fn update_static_masses(&mut self, body_vec: &[StaticBody], time: PhysicalTime) {
         self.static_masses.clear();
         for child_system in &self.child_systems {
             child_system.position.get_position(time);
@@ -104,20 +258,23 @@ impl GravitySystemTree {
         }
     }
 
+    #[cfg(feature = "render")]
     pub fn update_visual_objects(
         &self,
         body_store: &BodyStore,
+        entity_map: &BodyEntityMap,
         object_query: &mut Query<(&mut VisualObjectData, &mut Visibility)>,
         camera: &CameraState,
         is_parent_visible: bool,
         interpolation_factor: f64,
     ) {
-        let is_system_visible = camera.get_scale() as f64*self.radius > 2.5;
+        let is_system_visible = camera.get_scale() as f64*self.radius > MIN_VISIBLE_SYSTEM_RADIUS;
 
         for i in &self.dynamic_body_indices {
-            let e = unsafe { body_store.dynamic_entities.get_unchecked(*i) };
-            let db = unsafe { body_store.dynamic_bodies.get_unchecked(*i) };
-            let Ok((mut vo, mut vis)) = object_query.get_mut(*e) else { continue };
+            let id = unsafe { body_store.dynamic_ids.get_unchecked(*i) };
+            let db = body_store.dynamic_bodies.at(*i);
+            let Some(e) = entity_map.get_entity(*id) else { continue };
+            let Ok((mut vo, mut vis)) = object_query.get_mut(e) else { continue };
             vo.position = db.get_interpolated_absolute_position(interpolation_factor);
             vo.velocity = db.get_interpolated_relative_velocity(interpolation_factor);
             *vis = if is_system_visible { Visibility::Visible } else { Visibility::Hidden };
@@ -126,91 +283,178 @@ impl GravitySystemTree {
         // If this system is not visible but the parent is visible, then only draw the center body of the system
         // IMPORTANT: If the system does not have a center body and instead has a smaller system at the center then the entire system will disappear with no mini object point
         for (vec_index, store_index) in self.static_body_indices.iter().enumerate() {
-            let e = unsafe { body_store.static_entities.get_unchecked(*store_index) };
+            let id = unsafe { body_store.static_ids.get_unchecked(*store_index) };
             let sb = unsafe { body_store.static_bodies.get_unchecked(*store_index) };
-            let Ok((mut vo, mut vis)) = object_query.get_mut(*e) else { continue };
+            let Some(e) = entity_map.get_entity(*id) else { continue };
+            let Ok((mut vo, mut vis)) = object_query.get_mut(e) else { continue };
             vo.position = sb.get_absolute_position();
             vo.velocity = sb.get_relative_velocity();
             *vis = if is_system_visible || (vec_index==0 && is_parent_visible) { Visibility::Visible } else { Visibility::Hidden };
         }
 
         for child_system in &self.child_systems {
-            child_system.update_visual_objects(body_store, object_query, camera, is_system_visible, interpolation_factor)
+            child_system.update_visual_objects(body_store, entity_map, object_query, camera, is_system_visible, interpolation_factor)
+        }
+    }
+
+    /// Find the system with the provided id in this system or its children
+    pub fn find_system(&self, id: SystemId) -> Option<&GravitySystemTree> {
+        if self.id == id { return Some(self) }
+        self.child_systems.iter().find_map(|s| s.find_system(id))
+    }
+
+    /// Find the system that directly holds the dynamic body at the provided body store index
+    pub fn find_dynamic_body_system(&self, index: usize) -> Option<&GravitySystemTree> {
+        if self.dynamic_body_indices.contains(&index) { return Some(self) }
+        self.child_systems
+            .iter()
+            .filter(|s| s.total_child_dynamic_bodies > 0)
+            .find_map(|s| s.find_dynamic_body_system(index))
+    }
+
+    /// Find the system that directly holds the static body at the provided body store index
+    pub fn find_static_body_system(&self, index: usize) -> Option<&GravitySystemTree> {
+        if self.static_body_indices.contains(&index) { return Some(self) }
+        self.child_systems.iter().find_map(|s| s.find_static_body_system(index))
+    }
+
+
+    /// Replace the static body index with the dynamic body index in the system that holds it. \
+    /// The system and its parents gain a dynamic body and lose the static body's mu. Returns false if no system holds the static body.
+    pub(super) fn replace_static_with_dynamic(&mut self, static_index: usize, dynamic_index: usize, mu: f64) -> bool {
+        let found = if let Some(i) = self.static_body_indices.iter().position(|i| *i == static_index) {
+            self.static_body_indices.remove(i);
+            self.dynamic_body_indices.push(dynamic_index);
+            true
+        } else {
+            self.child_systems.iter_mut().any(|s| s.replace_static_with_dynamic(static_index, dynamic_index, mu))
+        };
+        if found {
+            self.total_child_dynamic_bodies += 1;
+            self.mu -= mu;
+        }
+        found
+    }
+
+    /// Replace the dynamic body index with the static body index in the system that holds it. \
+    /// The system and its parents lose a dynamic body and gain the static body's mu. Returns false if no system holds the dynamic body.
+    pub(super) fn replace_dynamic_with_static(&mut self, dynamic_index: usize, static_index: usize, mu: f64) -> bool {
+        if self.total_child_dynamic_bodies == 0 { return false }
+        let found = if let Some(i) = self.dynamic_body_indices.iter().position(|i| *i == dynamic_index) {
+            self.dynamic_body_indices.remove(i);
+            self.static_body_indices.push(static_index);
+            true
+        } else {
+            self.child_systems.iter_mut().any(|s| s.replace_dynamic_with_static(dynamic_index, static_index, mu))
+        };
+        if found {
+            self.total_child_dynamic_bodies -= 1;
+            self.mu += mu;
+        }
+        found
+    }
+
+    /// Add the dynamic body index to the system with the provided id, updating the dynamic body counts of the system and its parents. \
+    /// Returns false if there is no such system.
+    pub(super) fn add_dynamic_index(&mut self, id: SystemId, index: usize) -> bool {
+        let found = if self.id == id {
+            self.dynamic_body_indices.push(index);
+            true
+        } else {
+            self.child_systems.iter_mut().any(|s| s.add_dynamic_index(id, index))
+        };
+        if found {
+            self.total_child_dynamic_bodies += 1;
+        }
+        found
+    }
+    /// Remove the dynamic body index from the system that holds it, updating the dynamic body counts of the system and its parents. \
+    /// Returns false if no system holds it.
+    pub(super) fn remove_dynamic_index(&mut self, index: usize) -> bool {
+        if self.total_child_dynamic_bodies == 0 { return false }
+        let found = if let Some(i) = self.dynamic_body_indices.iter().position(|i| *i == index) {
+            self.dynamic_body_indices.remove(i);
+            true
+        } else {
+            self.child_systems.iter_mut().any(|s| s.remove_dynamic_index(index))
+        };
+        if found {
+            self.total_child_dynamic_bodies -= 1;
         }
+        found
     }
 
+    /// Point every reference to the dynamic body index from at the index to instead. Used after a body is swap removed from the body store.
+    pub(super) fn remap_dynamic_index(&mut self, from: usize, to: usize) {
+        if self.total_child_dynamic_bodies == 0 { return }
+        self.dynamic_body_indices.iter_mut().filter(|i| **i == from).for_each(|i| *i = to);
+        self.child_systems.iter_mut().for_each(|s| s.remap_dynamic_index(from, to));
+    }
+    /// Point every reference to the static body index from at the index to instead. Used after a body is swap removed from the body store.
+    pub(super) fn remap_static_index(&mut self, from: usize, to: usize) {
+        self.static_body_indices.iter_mut().filter(|i| **i == from).for_each(|i| *i = to);
+        self.child_systems.iter_mut().for_each(|s| s.remap_static_index(from, to));
+    }
 
     /// Clone the system tree, retaining only the dynamic body index \
     /// The provided index will be replaced with 0 in the result
     pub fn retain_clone(&self, index: usize) -> Self {
-        let child_systems = self.child_systems.iter().map(|s| s.retain_clone(index)).collect_vec();
-        let dynamic_body_indices = if self.dynamic_body_indices.contains(&index) {vec![0]} else {vec![]};
+        self.retain_clone_many(&[index])
+    }
+    /// Clone the system tree, retaining only the provided dynamic body indices \
+    /// Each index is replaced with its position in the slice in the result
+    pub fn retain_clone_many(&self, indices: &[usize]) -> Self {
+        let child_systems = self.child_systems.iter().map(|s| s.retain_clone_many(indices)).collect_vec();
+        let dynamic_body_indices = indices.iter().positions(|i| self.dynamic_body_indices.contains(i)).collect_vec();
         let total_child_dynamic_bodies = child_systems.iter().map(|s| s.total_child_dynamic_bodies).sum::<usize>() + dynamic_body_indices.len();
         Self {
+            id: self.id,
             dynamic_body_indices,
             static_body_indices: self.static_body_indices.clone(),
             child_systems,
             static_masses: self.static_masses.clone(),
+            dynamic_masses: vec![],
             time_step: self.time_step,
             radius: self.radius,
             position: self.position.clone(),
             parent_generator: self.parent_generator.clone(),
             mu: self.mu,
             total_child_dynamic_bodies,
+            child_grid: ChildSystemGrid::default(),
+            solver: self.solver,
+            barnes_hut: BarnesHutTree::default(),
         }
     }
 }
 
 
-/// Used to keep track of dynamic and static bodies and their associated entities
+/// Used to keep track of dynamic and static bodies and their associated ids
 #[derive(Default, Debug, Clone)]
 pub struct BodyStore {
-    pub dynamic_bodies: Vec<DynamicBody>,
-    pub dynamic_entities: Vec<Entity>,
+    pub dynamic_bodies: DynamicBodies,
+    /// Id of the dynamic body at each index. Only change through store methods so the index map stays in sync.
+    pub dynamic_ids: Vec<BodyId>,
+    /// Index of every dynamic body by id
+    dynamic_indices: HashMap<BodyId, usize>,
 
     pub static_bodies: Vec<StaticBody>,
-    pub static_entities: Vec<Entity>,
+    /// Id of the static body at each index. Only change through store methods so the index map stays in sync.
+    pub static_ids: Vec<BodyId>,
+    /// Index of every static body by id
+    static_indices: HashMap<BodyId, usize>,
+
+    /// The id given to the next body added to the store. Ids are never reused.
+    next_id: u64,
 }
 impl BodyStore {
     /// Performs one time step of gravity calculation \
     /// Note that this does not update all the static bodies in the body store. This method only updates static bodies when needed to calculate gravity. \
     /// This method assumes that the current position and velocity of dynamic bodies is new_time-1 \
-    pub fn update_dynamic_bodies(&mut self, system_tree: &mut GravitySystemTree, new_time: DiscreteGravitySystemTime) {
-        self.update_dynamic_bodies_recursive(system_tree, new_time, DVec2::ZERO, DVec2::ZERO, &mut vec![]);
-    }
-    pub fn update_dynamic_bodies_recursive(
-        &mut self,
-        system_tree: &mut GravitySystemTree,
-        new_time: DiscreteGravitySystemTime,
-        parent_pos: BodyPosition,
-        parent_vel: BodyVelocity,
-        elevator: &mut Vec<usize>,
-    ) {
-        let new_ftime = new_time as GravitySystemTime;
-        if system_tree.dynamic_body_indices.len() != 0 {
-            let should_accelerate = new_time % system_tree.time_step == 0;
-            if should_accelerate {
-                system_tree.calculate_gravity(new_ftime-1., &self.static_bodies, &mut self.dynamic_bodies);
-            }
-            system_tree.move_dynamic_bodies(new_time, &mut self.dynamic_bodies, should_accelerate, parent_pos, parent_vel);
-        } 
-        
-
-        let mut child_elevator = vec![];
-        for child_system in &mut system_tree.child_systems {
-            if child_system.total_child_dynamic_bodies < 1 { continue }
-            let (child_pos, child_vel) = child_system.position.get_position_and_velocity(new_ftime); // Should current time or new time be used here? I think new time since its used to set absolute position of dynamic bodies
-            self.update_dynamic_bodies_recursive(
-                child_system,
-                new_time,
-                parent_pos+child_pos,
-                parent_vel+child_vel,
-                &mut child_elevator
-            )
-        }
-        system_tree.dynamic_body_indices.extend_from_slice(&child_elevator);
-
-        system_tree.ascend_or_descend_bodies(new_ftime, &mut self.dynamic_bodies, elevator);
+    /// Each tick is tick_length seconds long.
+    pub fn update_dynamic_bodies(&mut self, system_tree: &mut GravitySystemTree, new_time: DiscreteGravitySystemTime, tick_length: f64) {
+        let dynamic_bodies = self.dynamic_bodies.as_ptr();
+        let tick = TickContext { new_time, tick_length, parent_pos: DVec2::ZERO, parent_vel: DVec2::ZERO };
+        system_tree.update_dynamic_bodies_recursive(&self.static_bodies, dynamic_bodies, tick, &mut vec![]);
     }
 
 
@@ -218,10 +462,10 @@ impl BodyStore {
 
     /// Recurse through the tree and set the position and velocity of all static bodies. \
     /// Only use this before updating visual objects since static bodies are only updated selectively when calculating gravity. \
-    pub fn update_static_bodies(&mut self, system_tree: &GravitySystemTree, time: GravitySystemTime) {
-        self.update_static_bodies_recursive(system_tree, time as f64, (DVec2::ZERO, DVec2::ZERO));
+    pub fn update_static_bodies(&mut self, system_tree: &GravitySystemTree, time: PhysicalTime) {
+        self.update_static_bodies_recursive(system_tree, time, (DVec2::ZERO, DVec2::ZERO));
     }
-    fn update_static_bodies_recursive(&mut self, system_tree: &GravitySystemTree, time: GravitySystemTime, parent_stats: (DVec2, DVec2)) {
+    fn update_static_bodies_recursive(&mut self, system_tree: &GravitySystemTree, time: PhysicalTime, parent_stats: (DVec2, DVec2)) {
         for i in system_tree.static_body_indices.iter().cloned() {
             let static_body = unsafe { self.static_bodies.get_unchecked_mut(i) };
             static_body.set_to_time_with_parent_stats(time, parent_stats)
@@ -233,53 +477,95 @@ impl BodyStore {
     }
 
 
-    /// Spawns visual objects using the dynamic and static bodies currently in the body store. \
-    /// Populates the dynamic and static entities arrays with the visual objects that were just spawned. \
-    pub fn spawn_visual_objects(&mut self, commands: &mut Commands) {
-        self.dynamic_entities.clear();
-        for db in &self.dynamic_bodies {
-            let bundle = VisualObjectBundle::new(VisualObjectData::from_dynamic_body(db));
-            let e = commands.spawn(bundle).id();
-            self.dynamic_entities.push(e);
-        }
-
-        self.static_entities.clear();
-        for sb in &self.static_bodies {
-            let bundle = VisualObjectBundle::new(VisualObjectData::from_static_body(sb));
-            let e = commands.spawn(bundle).id();
-            self.static_entities.push(e);
-        }
-    }
     /// Insert a dynamic body into the store and return the index used to access it. \
     /// This is only used when building the system.
     pub fn add_dynamic_body_to_store(&mut self, body: DynamicBody) -> usize {
-        self.dynamic_bodies.push(body);
-        self.dynamic_bodies.len()-1
+        let id = self.new_id();
+        self.add_dynamic_body_with_id(body, id)
     }
     /// Insert a static body into the store and return the index used to access it. \
     /// This is only used when building the system. \
     /// THIS METHOD DOES NOT MUTATE THE SYSTEM TREE. If you want to add a brand new body to the system tree, you will need to consult the system manager methods.
     pub fn add_static_body_to_store(&mut self, body: StaticBody) -> usize {
+        let id = self.new_id();
+        self.add_static_body_with_id(body, id)
+    }
+    /// Insert a dynamic body that keeps an id it was already given
+    pub(super) fn add_dynamic_body_with_id(&mut self, body: DynamicBody, id: BodyId) -> usize {
+        self.dynamic_ids.push(id);
+        let index = self.dynamic_bodies.push(body);
+        self.dynamic_indices.insert(id, index);
+        index
+    }
+    /// Insert a static body that keeps an id it was already given
+    pub(super) fn add_static_body_with_id(&mut self, body: StaticBody, id: BodyId) -> usize {
         self.static_bodies.push(body);
+        self.static_ids.push(id);
+        self.static_indices.insert(id, self.static_bodies.len()-1);
         self.static_bodies.len()-1
     }
+    /// Remove the dynamic body at the index by moving the last dynamic body into its place. \
+    /// Returns the old index of the body that was moved, if one was. The system tree has to be remapped to match.
+    pub(super) fn swap_remove_dynamic_body(&mut self, index: usize) -> Option<usize> {
+        let last = self.dynamic_bodies.len()-1;
+        self.dynamic_bodies.swap_remove(index);
+        self.dynamic_indices.remove(&self.dynamic_ids.swap_remove(index));
+        if let Some(moved) = self.dynamic_ids.get(index) {
+            self.dynamic_indices.insert(*moved, index);
+        }
+        (index != last).then_some(last)
+    }
+    /// Remove the static body at the index by moving the last static body into its place. \
+    /// Returns the removed body and the old index of the body that was moved, if one was. The system tree has to be remapped to match.
+    pub(super) fn swap_remove_static_body(&mut self, index: usize) -> (StaticBody, Option<usize>) {
+        let last = self.static_bodies.len()-1;
+        self.static_indices.remove(&self.static_ids.swap_remove(index));
+        if let Some(moved) = self.static_ids.get(index) {
+            self.static_indices.insert(*moved, index);
+        }
+        (self.static_bodies.swap_remove(index), (index != last).then_some(last))
+    }
+    fn new_id(&mut self) -> BodyId {
+        let id = BodyId(self.next_id);
+        self.next_id += 1;
+        id
+    }
 
+    /// Get the index of the dynamic body with the provided id
+    pub fn get_dynamic_index(&self, id: BodyId) -> Option<usize> {
+        self.dynamic_indices.get(&id).copied()
+    }
+    /// Get the index of the static body with the provided id
+    pub fn get_static_index(&self, id: BodyId) -> Option<usize> {
+        self.static_indices.get(&id).copied()
+    }
+    pub fn get_dynamic_body(&self, id: BodyId) -> Option<DynamicBodyRef<'_>> {
+        self.dynamic_bodies.get(self.get_dynamic_index(id)?)
+    }
+    pub fn get_static_body(&self, id: BodyId) -> Option<&StaticBody> {
+        self.get_static_index(id).map(|i| &self.static_bodies[i])
+    }
 
-    /// Clone the body store, retaining only the dynamic body associated with the provided entity
-    pub fn retain_clone(&self, entity: Entity) -> Option<(Self, usize)> {
-        let Some(idx) = self.dynamic_entities
-            .iter()
-            .position(|e| *e == entity)
-            else { return None };
-        
-        return Some((
+
+    /// Clone the body store, retaining only the dynamic body associated with the provided id
+    pub fn retain_clone(&self, id: BodyId) -> Option<(Self, usize)> {
+        self.retain_clone_many(&[id]).map(|(store, indices)| (store, indices[0]))
+    }
+    /// Clone the body store, retaining only the dynamic bodies associated with the provided ids, in the same order. \
+    /// Also returns the indices the bodies had in this store. None if any of the ids isn't a dynamic body.
+    pub fn retain_clone_many(&self, ids: &[BodyId]) -> Option<(Self, Vec<usize>)> {
+        let indices = ids.iter().map(|id| self.get_dynamic_index(*id)).collect::<Option<Vec<_>>>()?;
+        Some((
             Self {
-                dynamic_bodies: vec![self.dynamic_bodies[idx].clone()],
-                dynamic_entities: vec![entity],
+                dynamic_bodies: self.dynamic_bodies.retain_clone_many(&indices),
+                dynamic_ids: ids.to_vec(),
+                dynamic_indices: ids.iter().enumerate().map(|(i, id)| (*id, i)).collect(),
                 static_bodies: self.static_bodies.clone(),
-                static_entities: self.static_entities.clone()
+                static_ids: self.static_ids.clone(),
+                static_indices: self.static_indices.clone(),
+                next_id: self.next_id,
             },
-            idx
+            indices
         ))
     }
 }
@@ -290,16 +576,174 @@ impl BodyStore {
 impl Default for GravitySystemTree {
     fn default() -> Self {
         Self {
+            id: SystemId(0),
             dynamic_body_indices: vec![],
             static_body_indices: vec![],
             child_systems: vec![],
             static_masses: vec![], 
+            dynamic_masses: vec![],
             time_step: 1,
             radius: 1.,
             position: StaticPosition::Still,
             parent_generator: StaticGenerator::new(),
             mu: 0.,
             total_child_dynamic_bodies: 0,
+            child_grid: ChildSystemGrid::default(),
+            solver: GravitySolver::Direct,
+            barnes_hut: BarnesHutTree::default(),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::gravity_system_tree::system_manager::GravitySystemManager;
+    use super::*;
+
+    fn belt(rng: &mut StdRng, count: usize, min_radius: f64, max_radius: f64, mu: f64) -> Vec<DynamicBody> {
+        (0..count).map(|_| {
+            let radius = rng.gen_range(min_radius..max_radius);
+            let angle = rng.gen_range(0. ..std::f64::consts::TAU);
+            let speed = (mu / radius).sqrt() * rng.gen_range(0.8..1.2);
+            let direction = DVec2::from_angle(angle);
+            DynamicBody::new(direction * radius, direction.perp() * speed, 1., 1., Color::WHITE, "".into())
+        }).collect()
+    }
+
+    #[test]
+    fn parallel_update_matches_bodies_updated_alone() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let star_mass = 1e22;
+        let planet_mass = 1e19;
+        let planets = (0..4).map(|i| {
+            let orbit_radius = 4e4 + i as f64 * 1e4;
+            GravitySystemBuilder::new()
+                .with_position(StaticPosition::Circular { radius: orbit_radius, speed: (star_mass * crate::G / orbit_radius).sqrt() / orbit_radius, start_angle: i as f64 })
+                .with_radius(3000.)
+                .with_time_step(1)
+                .with_static_bodies(&[StaticBody::new(StaticPosition::Still, planet_mass, 10., Color::WHITE, "".into())])
+                .with_dynamic_bodies(&belt(&mut rng, 150, 500., 3500., planet_mass * crate::G))
+        }).collect::<Vec<_>>();
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, star_mass, 100., Color::WHITE, "".into())])
+            .with_dynamic_bodies(&belt(&mut rng, 1000, 3e4, 8e4, star_mass * crate::G))
+            .with_children(&planets);
+
+        let mut manager = GravitySystemManager::new(system);
+        let ids = manager.body_store.dynamic_ids.iter().cloned().step_by(50).collect::<Vec<_>>();
+        let mut alone = ids.iter().map(|id| manager.retain_clone(*id).unwrap()).collect::<Vec<_>>();
+        for _ in 0..2000 {
+            manager.step();
+            alone.iter_mut().for_each(|m| m.step());
+        }
+
+        let transitions = manager.body_store.dynamic_bodies.iter().map(|b| b.get_soi_transitions()).sum::<u64>();
+        assert!(transitions > 0, "bodies should move between systems during the test");
+        for (id, alone) in ids.iter().zip(&alone) {
+            let body = manager.body_store.get_dynamic_body(*id).unwrap();
+            let alone_body = alone.body_store.dynamic_bodies.at(0);
+            assert_eq!(body.get_current_absolute_position(), alone_body.get_current_absolute_position());
+            assert_eq!(body.get_current_absolute_velocity(), alone_body.get_current_absolute_velocity());
+            assert_eq!(body.get_soi_transitions(), alone_body.get_soi_transitions());
+        }
+    }
+
+    #[test]
+    fn mutual_gravity_pulls_bodies_in_same_system() {
+        let mass = 1e18;
+        let separation = 200.;
+        // Equal masses orbiting their shared center
+        let speed = (mass * crate::G / (2. * separation)).sqrt();
+        let pair = [
+            DynamicBody::new(DVec2::X * separation / 2., DVec2::Y * speed, mass, 1., Color::WHITE, "".into()).with_mutual_gravity(true),
+            DynamicBody::new(DVec2::NEG_X * separation / 2., DVec2::NEG_Y * speed, mass, 1., Color::WHITE, "".into()).with_mutual_gravity(true),
+        ];
+        let massless = DynamicBody::new(DVec2::Y * 5000., DVec2::ZERO, mass, 1., Color::WHITE, "".into());
+        let run = |bodies: &[DynamicBody]| {
+            let system = GravitySystemBuilder::new()
+                .with_position(StaticPosition::Still)
+                .with_radius(1e6)
+                .with_time_step(1)
+                .with_dynamic_bodies(bodies);
+            let mut manager = GravitySystemManager::new(system);
+            for _ in 0..20_000 {
+                manager.step();
+            }
+            manager
+        };
+        let manager = run(&[pair[0].clone(), pair[1].clone(), massless]);
+        let pair_only = run(&pair);
+
+        let bodies = &manager.body_store.dynamic_bodies;
+        let (a, b) = (bodies.at(0), bodies.at(1));
+        // Equal and opposite pulls keep the total momentum at zero and the pair bound
+        let momentum = a.get_current_relative_velocity() + b.get_current_relative_velocity();
+        assert!(momentum.length() < speed * 1e-9, "{momentum}");
+        let distance = a.get_current_relative_position().distance(b.get_current_relative_position());
+        assert!((distance - separation).abs() < separation * 0.01, "{distance}");
+        // The massless body falls towards the pair without pulling on it
+        assert!(bodies.at(2).get_current_relative_position().y < 5000.);
+        assert_eq!(a.get_current_relative_position(), pair_only.body_store.dynamic_bodies.at(0).get_current_relative_position());
+    }
+
+    #[test]
+    fn clone_keeps_barnes_hut_solver() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mass = 1e17;
+        let bodies = (0..200).map(|_| {
+            let radius = rng.gen_range(1000. ..5000.);
+            let direction = DVec2::from_angle(rng.gen_range(0. ..std::f64::consts::TAU));
+            let speed = (200. * mass * crate::G / radius).sqrt();
+            DynamicBody::new(direction * radius, direction.perp() * speed, mass, 1., Color::WHITE, "".into()).with_mutual_gravity(true)
+        }).collect::<Vec<_>>();
+        let system = GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_solver(GravitySolver::BarnesHut { opening_angle: 0.8 })
+            .with_dynamic_bodies(&bodies);
+        let mut manager = GravitySystemManager::new(system);
+        let mut predicted = manager.retain_clone_many(&manager.body_store.dynamic_ids.clone()).unwrap();
+        for _ in 0..200 {
+            manager.step();
+            predicted.step();
+        }
+        for (live, predicted) in manager.body_store.dynamic_bodies.iter().zip(predicted.body_store.dynamic_bodies.iter()) {
+            assert_eq!(live.get_current_absolute_position(), predicted.get_current_absolute_position());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn id_lookup_follows_swap_removes() {
+        let mut store = BodyStore::default();
+        let body = || DynamicBody::new(DVec2::ZERO, DVec2::ZERO, 1., 1., Color::WHITE, "".into());
+        for _ in 0..4 {
+            store.add_dynamic_body_to_store(body());
+        }
+        let ids = store.dynamic_ids.clone();
+        assert_eq!(store.swap_remove_dynamic_body(1), Some(3));
+        assert_eq!(store.get_dynamic_index(ids[1]), None);
+        assert_eq!(store.get_dynamic_index(ids[3]), Some(1));
+        assert_eq!(store.swap_remove_dynamic_body(2), None);
+        assert_eq!(store.get_dynamic_index(ids[2]), None);
+        store.add_dynamic_body_with_id(body(), ids[2]);
+        for (index, id) in store.dynamic_ids.iter().enumerate() {
+            assert_eq!(store.get_dynamic_index(*id), Some(index));
+        }
+    }
+
+    #[test]
+    fn zoom_fade_starts_at_visible_system_radius() {
+        assert_eq!(get_zoom_fade(0., 10.), 0.);
+        assert_eq!(get_zoom_fade(MIN_VISIBLE_SYSTEM_RADIUS, 10.), 0.);
+        assert!((get_zoom_fade(MIN_VISIBLE_SYSTEM_RADIUS * 5.5, 10.) - 0.5).abs() < 1e-12);
+        assert_eq!(get_zoom_fade(MIN_VISIBLE_SYSTEM_RADIUS * 10., 10.), 1.);
+        assert_eq!(get_zoom_fade(1e12, 10.), 1.);
+    }
+}
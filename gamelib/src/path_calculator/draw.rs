@@ -0,0 +1,302 @@
+use bevy::{math::DVec2, prelude::*, utils::HashMap};
+use bevy_egui::{egui::{self, Area, Label, RichText, Sense}, EguiContexts};
+
+use crate::{gravity_system_tree::{calendar::format_duration, frame::{FramePlacement, FrameTransform}, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{DiscreteGravitySystemTime, GravitySystemTime, PhysicalTime}}, pseudo_camera::camera::CameraState, visual_object::{BodyEntityMap, DrawOptions, FuturePathLineConfig, SelectedObjects, SimulationState, TrailMode, VisualObjectData}};
+
+use super::{FuturePath, FuturePaths, PathCalculator, PathEventKind, PredictionPool, PredictionPriority, Trail};
+
+
+
+/// Color of the part of a path that was calculated before the simulation changed
+const STALE_PATH_COLOR: Color = Color::srgb(0.45, 0.45, 0.45);
+/// Radius of path event markers in screen pixels
+const EVENT_MARKER_RADIUS: f32 = 5.;
+const CLOSEST_APPROACH_COLOR: Color = Color::srgb(1., 0.4, 1.);
+
+
+
+/// How positions along predicted paths are placed on screen at the current time
+pub struct PathProjection {
+    /// Physical time that is drawn
+    time: PhysicalTime,
+    tick_length: f64,
+    /// Transform and current placement of the reference frame. The local frame has none.
+    frame: Option<(FrameTransform, FramePlacement)>,
+}
+impl PathProjection {
+    pub fn new(frame: Option<FrameTransform>, time: GravitySystemTime, tick_length: f64) -> Self {
+        let time = time * tick_length;
+        Self { time, tick_length, frame: frame.map(|f| { let placement = f.get_placement(time); (f, placement) }) }
+    }
+
+    /// Where to draw a position relative to a generator at a tick. \
+    /// Positions are made absolute with the generator at their own tick so parts of a path in different systems line up.
+    pub fn place(&self, generator: &StaticGenerator, relative: DVec2, tick: DiscreteGravitySystemTime) -> DVec2 {
+        match &self.frame {
+            None => generator.get_position(self.time) + relative,
+            Some((frame, placement)) => {
+                let time = tick as f64 * self.tick_length;
+                frame.project(generator.get_position(time) + relative, time, placement)
+            }
+        }
+    }
+}
+
+impl PathCalculator {
+    pub fn draw_path(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, projection: &PathProjection, current_position: &DVec2) {
+        let first_segment = if !self.paths.is_empty() { &self.paths } else { &self.stale_paths };
+        let first_position = first_segment.relative_path_segments
+            .front()
+            .and_then(|fp| fp.path.front().map(|(t, rp)| projection.place(&fp.generator, *rp, *t)));
+        if let Some(position) = first_position {
+            gizmos.line_2d(camera.physics_to_world_pos(current_position), camera.physics_to_world_pos(&position), Color::WHITE);
+        }
+
+        self.stale_paths.draw_relative_path(gizmos, camera, projection, STALE_PATH_COLOR);
+        self.paths.draw_relative_path(gizmos, camera, projection, Color::WHITE);
+    }
+}
+
+impl FuturePaths {
+    pub fn draw_relative_path(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, projection: &PathProjection, color: Color) {
+        for path in &self.relative_path_segments {
+            path.draw(projection, gizmos, camera, color);
+        }
+    }
+}
+
+impl FuturePath {
+    pub fn draw(&self, projection: &PathProjection, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, color: Color) {
+        if projection.frame.is_some() {
+            let iter = self.path.iter().map(|(t, p)| camera.physics_to_world_pos(&projection.place(&self.generator, *p, *t)));
+            gizmos.linestrip_2d(iter, color);
+            return
+        }
+        let center_pos = self.generator.get_position(projection.time);
+        if center_pos == DVec2::ZERO {
+            self.draw_without_center_position(gizmos, camera, color);
+        } else {
+            self.draw_with_center_position(center_pos, gizmos, camera, color);
+        }
+    }
+    fn draw_with_center_position(&self, center_pos: DVec2, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, color: Color) {
+        let iter = self.path.iter().map(|(_, p)| camera.physics_to_world_pos(&(center_pos+*p)));
+        gizmos.linestrip_2d(iter, color);
+    }
+    fn draw_without_center_position(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, color: Color) {
+        let iter = self.path.iter().map(|(_, p)| camera.physics_to_world_pos(p));
+        gizmos.linestrip_2d(iter, color);
+    }
+}
+
+
+
+
+/// Predict paths again when the simulation changed since they were calculated. Paths that were predicted together are restarted together. \
+/// Path calculators of bodies that are no longer dynamic are removed.
+pub fn restart_outdated_paths(
+    mut path_query: Query<(Entity, &mut PathCalculator)>,
+    system_manager: Res<GravitySystemManager>,
+    prediction_pool: Res<PredictionPool>,
+    mut commands: Commands,
+) {
+    let mut groups: HashMap<u64, Vec<Mut<PathCalculator>>> = HashMap::new();
+    for (entity, path_calc) in path_query.iter_mut().filter(|(_, p)| p.is_outdated(&system_manager)) {
+        if path_calc.is_body_dynamic(&system_manager) {
+            groups.entry(path_calc.get_group()).or_default().push(path_calc);
+        } else {
+            commands.entity(entity).remove::<PathCalculator>();
+        }
+    }
+    for mut group in groups.into_values() {
+        PathCalculator::restart_group(&prediction_pool, &system_manager, &mut group.iter_mut().map(|p| &mut **p).collect::<Vec<_>>());
+    }
+}
+
+/// Take the points finished by prediction jobs and drop the ones that are in the past. \
+/// This runs even when paths aren't drawn so jobs keep an accurate count of stored points.
+pub fn receive_predicted_paths(
+    mut path_query: Query<&mut PathCalculator>,
+    sim_state: Res<SimulationState>,
+) {
+    let dtime = unsafe { sim_state.current_time.to_int_unchecked::<DiscreteGravitySystemTime>() + 1 };
+    for mut path_calc in path_query.iter_mut() {
+        path_calc.receive();
+        path_calc.drop_until_time(dtime);
+    }
+}
+
+/// Give the prediction job of the focused object priority over the rest
+pub fn prioritize_focused_path(
+    path_query: Query<(Entity, &PathCalculator)>,
+    selected_objects: Res<SelectedObjects>,
+) {
+    let focused = selected_objects.focused.as_ref().map(|(e, _)| *e);
+    for (entity, path_calc) in path_query.iter() {
+        let priority = if Some(entity) == focused { PredictionPriority::Focused } else { PredictionPriority::Background };
+        if path_calc.get_priority() != priority {
+            path_calc.set_priority(priority);
+        }
+    }
+}
+
+/// Draw the future path to the screen
+pub fn draw_path(
+    camera_query: Query<&CameraState>,
+    object_query: Query<(&PathCalculator, &VisualObjectData)>,
+    mut gizmos: Gizmos<FuturePathLineConfig>,
+    draw_options: Res<DrawOptions>,
+    sim_state: Res<SimulationState>,
+    system_manager: Res<GravitySystemManager>,
+) {
+    if draw_options.draw_future_path == false { return }
+    if object_query.is_empty() { return }
+
+    let Ok(camera) = camera_query.get_single() else { return };
+    let frame = system_manager.get_frame_transform(draw_options.reference_frame);
+
+    for (path_calc, VisualObjectData { position, .. }) in object_query.iter() {
+        let projection = PathProjection::new(frame.clone(), sim_state.current_time, path_calc.get_tick_length());
+        path_calc.draw_path(&mut gizmos, camera, &projection, position);
+    }
+}
+
+
+fn get_event_color(kind: &PathEventKind) -> Color {
+    match kind {
+        PathEventKind::Periapsis | PathEventKind::Apoapsis => Color::srgb(0.4, 0.7, 1.),
+        PathEventKind::SoiEntry(_) | PathEventKind::SoiExit(_) => Color::srgb(1., 0.8, 0.3),
+        PathEventKind::Impact(_) => Color::srgb(1., 0.3, 0.3),
+    }
+}
+
+/// Draw a labelled marker at every event ahead on predicted paths, and at the positions of a body and its target at their closest approach. \
+/// Clicking the label of a marker runs the simulation until the event.
+pub fn draw_path_events(
+    mut contexts: EguiContexts,
+    camera_query: Query<(&CameraState, &Camera, &GlobalTransform)>,
+    path_query: Query<(Entity, &PathCalculator)>,
+    mut gizmos: Gizmos<FuturePathLineConfig>,
+    draw_options: Res<DrawOptions>,
+    mut sim_state: ResMut<SimulationState>,
+    system_manager: Res<GravitySystemManager>,
+) {
+    if !draw_options.draw_future_path || !draw_options.draw_path_events { return }
+    let Ok((camera_state, camera, camera_gtrans)) = camera_query.get_single() else { return };
+    let ctx = contexts.ctx_mut();
+    // Space that isn't covered by panels
+    let visible = ctx.available_rect();
+    let frame = system_manager.get_frame_transform(draw_options.reference_frame);
+
+    for (entity, path_calc) in path_query.iter() {
+        let projection = PathProjection::new(frame.clone(), sim_state.current_time, path_calc.get_tick_length());
+        let time_left = |time: DiscreteGravitySystemTime| format_duration((time as f64 - sim_state.current_time) * path_calc.get_tick_length());
+        let mut markers = path_calc.get_events()
+            .map(|(generator, event)| {
+                let text = format!("{} in {}", event.kind.get_label(), time_left(event.time));
+                (projection.place(generator, event.relative_position, event.time), get_event_color(&event.kind), text, event.time)
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(approach) = path_calc.get_closest_approach() {
+            let body_pos = projection.place(&approach.generator, approach.body_position, approach.time);
+            let target_pos = projection.place(&approach.generator, approach.target_position, approach.time);
+            gizmos.line_2d(camera_state.physics_to_world_pos(&body_pos), camera_state.physics_to_world_pos(&target_pos), CLOSEST_APPROACH_COLOR);
+            gizmos.circle_2d(camera_state.physics_to_world_pos(&target_pos), EVENT_MARKER_RADIUS, CLOSEST_APPROACH_COLOR);
+            let text = format!("Closest approach {:.1} at {:.1}/s in {}", approach.distance, approach.relative_velocity.length(), time_left(approach.time));
+            markers.push((body_pos, CLOSEST_APPROACH_COLOR, text, approach.time));
+        }
+
+        for (i, (position, color, text, time)) in markers.into_iter().enumerate() {
+            let world_pos = camera_state.physics_to_world_pos(&position);
+            let Some(screen_pos) = camera.world_to_viewport(camera_gtrans, world_pos.extend(0.)) else { continue };
+            let screen_pos = egui::pos2(screen_pos.x, screen_pos.y);
+            if !visible.contains(screen_pos) { continue }
+
+            gizmos.circle_2d(world_pos, EVENT_MARKER_RADIUS, color);
+            Area::new(egui::Id::new(("path event", entity, i)))
+                .fixed_pos(screen_pos + egui::vec2(EVENT_MARKER_RADIUS + 2., -EVENT_MARKER_RADIUS))
+                .show(ctx, |ui| {
+                    let label = ui.add(Label::new(RichText::new(text).small()).sense(Sense::click()).selectable(false));
+                    if label.on_hover_text("Run until here").clicked() {
+                        sim_state.run_until = Some(time as f64);
+                        sim_state.running = true;
+                    }
+                });
+        }
+    }
+}
+
+
+
+/// Record the current position of every body that should have a trail, adding and removing trails as the trail mode and selection change. \
+/// Points older than the trail length are dropped. \
+/// New points are decimated with the trail tolerance at the current zoom, so trails in small systems keep their shape when zoomed in on.
+pub fn record_trails(
+    camera_query: Query<&CameraState>,
+    mut object_query: Query<(Entity, Option<&mut Trail>), With<VisualObjectData>>,
+    entity_map: Res<BodyEntityMap>,
+    selected_objects: Res<SelectedObjects>,
+    system_manager: Res<GravitySystemManager>,
+    draw_options: Res<DrawOptions>,
+    mut commands: Commands,
+) {
+    let time = system_manager.get_current_time();
+    let length = (draw_options.trail_length / system_manager.get_tick_length()) as DiscreteGravitySystemTime;
+    let Ok(camera) = camera_query.get_single() else { return };
+    let tolerance = draw_options.trail_tolerance / camera.get_scale() as f64;
+    let focused = selected_objects.focused.as_ref().map(|(e, _)| *e);
+
+    for (entity, trail) in object_query.iter_mut() {
+        let wanted = match draw_options.trail_mode {
+            TrailMode::Off => false,
+            TrailMode::Selected => Some(entity) == focused || selected_objects.selected.contains(&entity),
+            TrailMode::All => true,
+        };
+        let position = entity_map.get_body(entity).and_then(|id| system_manager.get_body_relative_position(id));
+        match (trail, position) {
+            (Some(mut trail), Some((generator, position))) if wanted => {
+                trail.record(generator, position, time, tolerance);
+                trail.drop_until_time(time.saturating_sub(length));
+            },
+            (None, Some((generator, position))) if wanted => {
+                let mut trail = Trail::default();
+                trail.record(generator, position, time, tolerance);
+                commands.entity(entity).insert(trail);
+            },
+            (Some(_), _) => { commands.entity(entity).remove::<Trail>(); },
+            (None, _) => {},
+        }
+    }
+}
+
+/// Draw the trails of visible bodies in the color of the body, fading out with age
+pub fn draw_trails(
+    camera_query: Query<&CameraState>,
+    object_query: Query<(&Trail, &VisualObjectData, &Visibility)>,
+    mut gizmos: Gizmos<FuturePathLineConfig>,
+    draw_options: Res<DrawOptions>,
+    sim_state: Res<SimulationState>,
+    system_manager: Res<GravitySystemManager>,
+) {
+    if draw_options.trail_mode == TrailMode::Off { return }
+    let Ok(camera) = camera_query.get_single() else { return };
+    let tick_length = system_manager.get_tick_length();
+    let projection = PathProjection::new(system_manager.get_frame_transform(draw_options.reference_frame), sim_state.current_time, tick_length);
+    let fade = |time: DiscreteGravitySystemTime, color: Color| {
+        let age = (sim_state.current_time - time as f64) * tick_length;
+        color.with_alpha((1. - age / draw_options.trail_length).clamp(0., 1.) as f32)
+    };
+
+    for (trail, VisualObjectData { position, color, .. }, visibility) in object_query.iter() {
+        if *visibility == Visibility::Hidden { continue }
+        for path in &trail.paths.relative_path_segments {
+            let iter = path.path.iter().map(|(t, p)| (camera.physics_to_world_pos(&projection.place(&path.generator, *p, *t)), fade(*t, *color)));
+            gizmos.linestrip_gradient_2d(iter);
+        }
+        let last = trail.paths.relative_path_segments.back().and_then(|fp| fp.path.back().map(|(t, p)| (projection.place(&fp.generator, *p, *t), *t)));
+        if let Some((last, time)) = last {
+            gizmos.line_gradient_2d(camera.physics_to_world_pos(&last), camera.physics_to_world_pos(position), fade(time, *color), *color);
+        }
+    }
+}
@@ -1,65 +1,278 @@
-use std::{collections::VecDeque, sync::{Arc, Mutex}, thread::{self, JoinHandle}};
+use std::{collections::VecDeque, sync::{atomic::{AtomicU64, Ordering}, mpsc::{channel, Receiver, TryRecvError}, Arc}};
 
-use bevy::{ecs::system, gizmos::gizmos, math::DVec2, prelude::*};
-use bevy_egui::egui::epaint::tessellator::path;
+use bevy::{math::DVec2, prelude::*, utils::synccell::SyncCell};
 use itertools::Itertools;
 
-use crate::{gravity_system_tree::{dynamic_body::DynamicBody, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{DiscreteGravitySystemTime, GravitySystemTime}}, pseudo_camera::camera::CameraState, visual_object::{DrawOptions, FuturePathLineConfig, SimulationState, VisualObjectData}};
+use crate::{export::PredictedPathRecord, gravity_system_tree::{body_id::BodyId, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::{DiscreteGravitySystemTime, PhysicalTime}}};
 
+/// Worker threads that run prediction jobs
+mod pool;
+pub use pool::*;
 
+/// Past positions of bodies
+mod trail;
+pub use trail::*;
 
-/// Spawn a background thread to calculate the future path of the selected object
+/// Drawing predicted paths to the screen
+#[cfg(feature = "render")]
+mod draw;
+#[cfg(feature = "render")]
+pub use draw::*;
+
+
+
+/// Points a path calculator takes from its job per call to receive, so a fast job can't hold up a frame
+const MAX_POINTS_PER_RECEIVE: usize = 50_000;
+/// Dropped points a path checks against new segments before keeping a point anyway, so nearly straight paths don't slow down insertion
+const MAX_SKIPPED_POINTS: usize = 64;
+
+
+
+/// How far ahead a path is predicted. \
+/// Jobs also stop when the body touches a static body, or once the render side holds MAX_PATH_POINTS points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PredictionHorizon {
+    /// Stop after the body went around the system it is in this many times. Counting starts over when the body changes system.
+    Orbits(f64),
+    /// Stop after this many physical seconds
+    Duration(PhysicalTime),
+    /// Stop when the body changes system. \
+    /// Collisions between dynamic bodies are not an event, since paths are predicted in a copy of the system without collision detection, so a path runs on through them.
+    NextEvent,
+}
+impl PredictionHorizon {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Orbits(_) => "Orbits",
+            Self::Duration(_) => "Duration",
+            Self::NextEvent => "Next Event",
+        }
+    }
+}
+
+/// How a path calculator predicts and stores its path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PredictionSettings {
+    pub horizon: PredictionHorizon,
+    /// Points are dropped as long as the path stays within this physical distance of them
+    pub tolerance: f64,
+    /// Body to find the closest approach to. A dynamic target is predicted along with the body.
+    pub target: Option<BodyId>,
+}
+impl Default for PredictionSettings {
+    fn default() -> Self {
+        Self { horizon: PredictionHorizon::Orbits(5.), tolerance: 10., target: None }
+    }
+}
+
+
+
+/// Used to tell which path calculators were started by the same job
+static NEXT_GROUP: AtomicU64 = AtomicU64::new(0);
+
+
+
+/// Predicted path of a dynamic body, calculated by a job on the prediction pool. \
+/// Points are handed over through a channel, so the render side never waits on the simulation. \
+/// The paths of several bodies can be predicted by one job, which steps the system once for all of them. \
+/// Dropping the path calculator stops the job from predicting its path.
 #[derive(Component)]
 pub struct PathCalculator {
-    paths: Arc<Mutex<FuturePaths>>,
-    _thread_handle: JoinHandle<()>,
+    body: BodyId,
+    /// Path calculators started together share a group and are restarted together
+    group: u64,
+    /// Physical seconds per tick of the simulation the path is calculated from
+    tick_length: f64,
+    /// Revision of the manager the path is calculated from
+    revision: u64,
+    settings: PredictionSettings,
+    paths: FuturePaths,
+    closest_approach: Option<ClosestApproach>,
+    /// Path calculated before the last restart. It is shown past the end of the new path until the new path catches up.
+    stale_paths: FuturePaths,
+    receiver: SyncCell<Receiver<PathChunk>>,
+    control: Arc<JobControl>,
 }
 impl PathCalculator {
-    /// Takes in a system manager and an entity for which to clone while retaining the provided entity. 
-    pub fn new(system_manager: &GravitySystemManager, entity: Entity) -> Self {
-        let mut new_system = system_manager.retain_clone(entity).unwrap();
-        let future_paths = Arc::new(Mutex::new(FuturePaths::default()));
-        let future_paths_copy = future_paths.clone();
-
-        let handle = thread::spawn(move || {
-            loop {
-                if Arc::strong_count(&future_paths) < 2 {
-                    break;
+    /// Takes in a system manager and the id of a dynamic body for which to clone while retaining the provided body. \
+    /// Returns None if the body is not a dynamic body.
+    pub fn new(pool: &PredictionPool, system_manager: &GravitySystemManager, id: BodyId, priority: PredictionPriority, settings: PredictionSettings) -> Option<Self> {
+        Self::new_group(pool, system_manager, &[id], priority, settings).pop()
+    }
+
+    /// Predict the paths of several bodies in one job. \
+    /// Returns a path calculator for every id that is a dynamic body, in the same order.
+    pub fn new_group(pool: &PredictionPool, system_manager: &GravitySystemManager, ids: &[BodyId], priority: PredictionPriority, settings: PredictionSettings) -> Vec<Self> {
+        let mut calculators = ids
+            .iter()
+            .filter(|id| system_manager.body_store.get_dynamic_index(**id).is_some())
+            .map(|id| {
+                // Replaced when the job is started
+                let (_, receiver) = channel();
+                Self {
+                    body: *id,
+                    group: 0,
+                    tick_length: system_manager.get_tick_length(),
+                    revision: system_manager.get_revision(),
+                    settings,
+                    paths: FuturePaths::default(),
+                    closest_approach: None,
+                    stale_paths: FuturePaths::default(),
+                    receiver: SyncCell::new(receiver),
+                    control: Arc::new(JobControl::new(priority)),
+                }
+            })
+            .collect_vec();
+        Self::restart_group(pool, system_manager, &mut calculators.iter_mut().collect_vec());
+        calculators
+    }
+
+    /// Cancel the jobs of the path calculators and predict their paths again from the current state of the manager, in one job. \
+    /// The bodies of the path calculators have to be dynamic bodies.
+    pub fn restart_group(pool: &PredictionPool, system_manager: &GravitySystemManager, calculators: &mut [&mut PathCalculator]) {
+        if calculators.is_empty() { return }
+        // Dynamic targets are predicted along with the bodies so their positions are known
+        let targets = calculators
+            .iter()
+            .filter_map(|c| c.settings.target)
+            .filter(|id| system_manager.body_store.get_dynamic_index(*id).is_some());
+        let ids = calculators.iter().map(|c| c.body).chain(targets).unique().collect_vec();
+        let Some(new_system) = system_manager.retain_clone_many(&ids) else { return };
+
+        let group = NEXT_GROUP.fetch_add(1, Ordering::Relaxed);
+        let requests = calculators.iter_mut().map(|calculator| {
+            let index = ids.iter().position(|id| *id == calculator.body).unwrap();
+            let (sender, receiver) = channel();
+            calculator.control.cancel();
+            calculator.control = Arc::new(JobControl::new(calculator.control.get_priority()));
+            calculator.receiver = SyncCell::new(receiver);
+            calculator.closest_approach = None;
+            // If the last restart hasn't produced anything yet, the path before it is still the best guess
+            if !calculator.paths.is_empty() {
+                calculator.stale_paths = std::mem::take(&mut calculator.paths);
+            }
+            calculator.group = group;
+            calculator.tick_length = system_manager.get_tick_length();
+            calculator.revision = system_manager.get_revision();
+            StreamRequest { index, settings: calculator.settings, sender, control: calculator.control.clone() }
+        }).collect();
+        pool.submit(new_system, requests);
+    }
+
+    /// Whether the manager changed since the path was calculated, for example because a body was edited or collided
+    pub fn is_outdated(&self, system_manager: &GravitySystemManager) -> bool {
+        self.revision != system_manager.get_revision()
+    }
+
+    /// Cancel the job and predict the path again from the current state of the manager, leaving the group it was in. \
+    /// The old path is kept as a stale path until the new one reaches the same time. \
+    /// Returns false and leaves the path alone if the body is no longer a dynamic body.
+    pub fn restart(&mut self, pool: &PredictionPool, system_manager: &GravitySystemManager) -> bool {
+        if !self.is_body_dynamic(system_manager) { return false }
+        Self::restart_group(pool, system_manager, &mut [self]);
+        true
+    }
+
+    pub fn is_body_dynamic(&self, system_manager: &GravitySystemManager) -> bool {
+        system_manager.body_store.get_dynamic_index(self.body).is_some()
+    }
+    pub fn get_group(&self) -> u64 { self.group }
+
+    pub fn get_settings(&self) -> PredictionSettings { self.settings }
+    /// Change the settings and predict the path again. Returns false if the body is no longer a dynamic body.
+    pub fn set_settings(&mut self, pool: &PredictionPool, system_manager: &GravitySystemManager, settings: PredictionSettings) -> bool {
+        self.settings = settings;
+        self.restart(pool, system_manager)
+    }
+
+    /// Move points the job has finished into the path. Never blocks.
+    pub fn receive(&mut self) {
+        let mut received = 0;
+        while received < MAX_POINTS_PER_RECEIVE {
+            match self.receiver.get().try_recv() {
+                Ok(chunk) => {
+                    received += chunk.points.len();
+                    self.process_chunk(chunk);
                 }
-                let mut paths = future_paths.lock().unwrap();
-                if !paths.should_stop() {
-                    new_system.step();
-                    let body = unsafe { new_system.body_store.dynamic_bodies.get_unchecked(0) };
-                    paths.process_new_body_state(body, new_system.get_current_time());
-                } else {
-                    return
+                Err(TryRecvError::Empty) => break,
+                // The job is done, so nothing of the stale path will be replaced
+                Err(TryRecvError::Disconnected) => {
+                    self.stale_paths = FuturePaths::default();
+                    break
                 }
             }
-        });
+        }
+        if let Some(time) = self.paths.last_time() {
+            self.stale_paths.drop_until_time(time);
+        }
+        self.control.set_stored_points(self.paths.len());
+    }
 
-        Self {
-            paths: future_paths_copy,
-            _thread_handle: handle,
+    fn process_chunk(&mut self, mut chunk: PathChunk) {
+        if let Some(closest_approach) = chunk.closest_approach.take() {
+            self.closest_approach = Some(closest_approach);
         }
+        self.paths.process_chunk(chunk, self.settings.tolerance);
     }
 
-    pub fn draw_path(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, time: GravitySystemTime, current_position: &DVec2) {
-        let mut paths = self.paths.lock().unwrap();
-        let dtime = unsafe { time.to_int_unchecked::<DiscreteGravitySystemTime>() + 1 };
-        paths.drop_until_time(dtime);
-
-        let first_position = paths.relative_path_segments
-            .front()
-            .map(|fp| 
-                fp.path
-                    .front()
-                    .map(|(_, rp)| fp.generator.get_position(time)+*rp)
-            ).flatten();
-        if let Some(position) = first_position {
-            gizmos.line_2d(camera.physics_to_world_pos(current_position), camera.physics_to_world_pos(&position), Color::WHITE);
+    /// Forget points that are no longer in the future. \
+    /// A closest approach that has passed is forgotten too, since later approaches weren't looked for.
+    pub fn drop_until_time(&mut self, time: DiscreteGravitySystemTime) {
+        if self.closest_approach.as_ref().is_some_and(|a| a.time < time) {
+            self.closest_approach = None;
         }
+        self.paths.drop_until_time(time);
+        self.stale_paths.drop_until_time(time);
+        self.control.set_stored_points(self.paths.len());
+    }
+
+    /// Every event ahead on the path, with the system the event position is relative to
+    pub fn get_events(&self) -> impl Iterator<Item = (&StaticGenerator, &PathEvent)> {
+        self.paths.relative_path_segments.iter().flat_map(|fp| fp.events.iter().map(move |e| (&fp.generator, e)))
+    }
+
+    /// Closest approach to the target of the settings found so far
+    pub fn get_closest_approach(&self) -> Option<&ClosestApproach> { self.closest_approach.as_ref() }
+
+    /// Whether part of the path shown is from before the last restart
+    pub fn has_stale_path(&self) -> bool { !self.stale_paths.is_empty() }
+
+    /// Stop calculating the path. Points calculated so far are kept.
+    pub fn cancel(&self) { self.control.cancel() }
+    pub fn is_cancelled(&self) -> bool { self.control.is_cancelled() }
 
-        paths.draw_relative_path(gizmos, camera, time);
+    pub fn set_priority(&self, priority: PredictionPriority) { self.control.set_priority(priority) }
+    pub fn get_priority(&self) -> PredictionPriority { self.control.get_priority() }
+
+    pub fn get_body(&self) -> BodyId { self.body }
+    pub fn get_tick_length(&self) -> f64 { self.tick_length }
+
+    /// Get every point of the predicted path calculated so far, with positions resolved to absolute coordinates
+    pub fn get_path_records(&self) -> Vec<PredictedPathRecord> {
+        self.paths.relative_path_segments
+            .iter()
+            .enumerate()
+            .flat_map(|(segment, fp)| fp.path.iter().map(move |(tick, relative_position)| {
+                let position = fp.generator.get_position(*tick as f64 * self.tick_length) + *relative_position;
+                PredictedPathRecord {
+                    body: self.body,
+                    segment,
+                    tick: *tick,
+                    x: position.x,
+                    y: position.y,
+                    relative_x: relative_position.x,
+                    relative_y: relative_position.y,
+                }
+            }))
+            .collect()
+    }
+}
+
+
+
+impl Drop for PathCalculator {
+    fn drop(&mut self) {
+        self.cancel();
     }
 }
 
@@ -71,27 +284,45 @@ pub struct FuturePaths {
     relative_path_segments: VecDeque<FuturePath>,
 }
 impl FuturePaths {
-    pub fn process_new_body_state(&mut self, body: &DynamicBody, time: DiscreteGravitySystemTime) {
+    pub fn process_chunk(&mut self, chunk: PathChunk, tolerance: f64) {
         let should_create_new_path = self.relative_path_segments
             .back()
-            .map_or(true, |fp| 
-                fp.generator.len() != body.get_parent_generator().len()
-            );
+            .map_or(true, |fp| fp.generator != chunk.generator);
         if should_create_new_path {
-            self.relative_path_segments.push_back(FuturePath::from_body(body, time));
-        } else {
-            self.relative_path_segments.back_mut().unwrap().insert_new_position(body.get_previous_relative_position(), time);
+            self.relative_path_segments.push_back(FuturePath::new(chunk.generator));
         }
+        let path = self.relative_path_segments.back_mut().unwrap();
+        for (time, position) in chunk.points {
+            path.insert_new_position(position, time, tolerance);
+        }
+        path.events.extend(chunk.events);
     }
 
-    pub fn should_stop(&self) -> bool {
-        self.relative_path_segments.iter().map(|rp| rp.len()).sum::<usize>() > 500_000
+    /// Add a point relative to a system, starting a new segment when the system is not the one of the last segment
+    pub fn insert_new_position(&mut self, generator: &StaticGenerator, position: DVec2, time: DiscreteGravitySystemTime, tolerance: f64) {
+        if self.relative_path_segments.back().is_none_or(|fp| fp.generator != *generator) {
+            self.relative_path_segments.push_back(FuturePath::new(generator.clone()));
+        }
+        self.relative_path_segments.back_mut().unwrap().insert_new_position(position, time, tolerance);
     }
 
-    pub fn draw_relative_path(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState, time: GravitySystemTime) {
-        for path in &self.relative_path_segments {
-            path.draw(time, gizmos, camera);
-        }
+    /// Number of points in every segment
+    pub fn len(&self) -> usize {
+        self.relative_path_segments.iter().map(|rp| rp.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relative_path_segments.iter().all(|rp| rp.path.is_empty())
+    }
+
+    /// Time of the first point
+    pub fn first_time(&self) -> Option<DiscreteGravitySystemTime> {
+        self.relative_path_segments.iter().find_map(|fp| fp.path.front().map(|(t, _)| *t))
+    }
+
+    /// Time of the last point
+    pub fn last_time(&self) -> Option<DiscreteGravitySystemTime> {
+        self.relative_path_segments.iter().rev().find_map(|fp| fp.path.back().map(|(t, _)| *t))
     }
 
     pub fn drop_until_time(&mut self, time: DiscreteGravitySystemTime) {
@@ -114,54 +345,45 @@ pub struct FuturePath {
     /// Whether or not the last position in the path queue should be retained or not. \
     /// This is useful for culling unnecessary points that fall on roughly the same line.
     last_is_removable: bool,
+    /// Points dropped since the second last point. The segment to a new point has to pass close to all of them.
+    skipped: Vec<DVec2>,
+    /// Events along this part of the path, in order
+    events: VecDeque<PathEvent>,
 }
 impl FuturePath {
-    pub fn from_body(body: &DynamicBody, time: DiscreteGravitySystemTime) -> Self {
-        let mut path = VecDeque::new();
-        path.push_back((time, body.get_previous_relative_position()));
+    pub fn new(generator: StaticGenerator) -> Self {
         Self {
-            path,
-            generator: body.get_parent_generator().clone(),
-            last_is_removable: false
+            path: VecDeque::new(),
+            generator,
+            last_is_removable: false,
+            skipped: vec![],
+            events: VecDeque::new(),
         }
     }
 
     pub fn len(&self) -> usize { self.path.len() }
 
-    pub fn insert_new_position(&mut self, new_position: DVec2, time: DiscreteGravitySystemTime) {
+    /// Add a point, dropping the last point if the path stays within tolerance of it and of every point dropped before it
+    pub fn insert_new_position(&mut self, new_position: DVec2, time: DiscreteGravitySystemTime, tolerance: f64) {
         if let Some((last, second_last)) = self.path.iter().rev().next_tuple() {
-            if (last.1 - second_last.1).angle_between(new_position - last.1).abs() < std::f64::consts::TAU/300. {
-                if self.last_is_removable { self.path.pop_back(); }
-                self.path.push_back((time, new_position));
-                self.last_is_removable = true;
+            let is_close = |p: &DVec2| distance_to_segment(*p, second_last.1, new_position) <= tolerance;
+            if self.last_is_removable && self.skipped.len() < MAX_SKIPPED_POINTS && is_close(&last.1) && self.skipped.iter().all(is_close) {
+                self.skipped.push(last.1);
+                self.path.pop_back();
             } else {
-                self.path.push_back((time, new_position));
-                self.last_is_removable = false;
+                self.skipped.clear();
             }
         } else {
-            self.path.push_back((time, new_position));
-            self.last_is_removable = false;
-        }
-    }
-
-    pub fn draw(&self, time: GravitySystemTime, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState) {
-        let center_pos = self.generator.get_position(time);
-        if center_pos == DVec2::ZERO {
-            self.draw_without_center_position(gizmos, camera);
-        } else {
-            self.draw_with_center_position(center_pos, gizmos, camera);
+            self.skipped.clear();
         }
-    }
-    fn draw_with_center_position(&self, center_pos: DVec2, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState) {
-        let iter = self.path.iter().map(|(_, p)| camera.physics_to_world_pos(&(center_pos+*p)));
-        gizmos.linestrip_2d(iter, Color::WHITE);
-    }
-    fn draw_without_center_position(&self, gizmos: &mut Gizmos<FuturePathLineConfig>, camera: &CameraState) {
-        let iter = self.path.iter().map(|(_, p)| camera.physics_to_world_pos(p));
-        gizmos.linestrip_2d(iter, Color::WHITE);
+        self.last_is_removable = !self.path.is_empty();
+        self.path.push_back((time, new_position));
     }
 
     fn drop_until_time(&mut self, time: DiscreteGravitySystemTime) {
+        while self.events.front().is_some_and(|e| e.time <= time) {
+            self.events.pop_front();
+        }
         let index = self.path
             .iter()
             .find_position(|(t, _)| *t > time)
@@ -175,26 +397,37 @@ impl FuturePath {
 }
 
 
+/// Distance from a point to the closest point on the segment between start and end
+fn distance_to_segment(point: DVec2, start: DVec2, end: DVec2) -> f64 {
+    let segment = end - start;
+    let length_squared = segment.length_squared();
+    if length_squared == 0. { return point.distance(start) }
+    let t = ((point - start).dot(segment) / length_squared).clamp(0., 1.);
+    point.distance(start + segment * t)
+}
 
 
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Draw the future path to the screen
-pub fn draw_path(
-    camera_query: Query<&CameraState>,
-    object_query: Query<(&PathCalculator, &VisualObjectData)>,
-    mut gizmos: Gizmos<FuturePathLineConfig>,
-    draw_options: Res<DrawOptions>,
-    sim_state: Res<SimulationState>
-) {
-    if draw_options.draw_future_path == false { return }
-    if object_query.is_empty() { return }
-
-    let Ok(camera) = camera_query.get_single() else { return };
-
-    for (path_calc, VisualObjectData { position, .. }) in object_query.iter() {
+    #[test]
+    fn decimated_circle_stays_within_tolerance() {
+        let radius = 1000.;
+        let circle = |i: u64| DVec2::from_angle(i as f64 * 0.01) * radius;
+        let mut path = FuturePath::default();
+        for i in 0..600 {
+            path.insert_new_position(circle(i), i, 1.);
+        }
 
-        path_calc.draw_path(&mut gizmos, camera, sim_state.current_time, position);
+        // Chords that stay within the tolerance of a circle are sqrt(8 * radius * tolerance) long, so most points are dropped
+        assert!(path.len() < 100, "{} points kept", path.len());
+        assert!((0..600).all(|i| {
+            let (_, previous) = path.path.iter().rev().find(|(t, _)| *t <= i).unwrap();
+            let next = path.path.iter().find(|(t, _)| *t >= i).map_or(previous, |(_, p)| p);
+            distance_to_segment(circle(i), *previous, *next) <= 1.
+        }));
     }
 }
\ No newline at end of file
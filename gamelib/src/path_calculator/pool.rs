@@ -0,0 +1,699 @@
+use std::{sync::{atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering}, mpsc::Sender, Arc, Condvar, Mutex}, thread::{self, JoinHandle}};
+
+use bevy::{math::DVec2, prelude::Resource};
+
+use crate::gravity_system_tree::{body_id::BodyId, dynamic_body::DynamicBodyRef, static_generator::StaticGenerator, system_manager::GravitySystemManager, system_tree::DiscreteGravitySystemTime};
+
+use super::{PredictionHorizon, PredictionSettings};
+
+
+/// Ticks a worker simulates before going back to the queue, so higher priority jobs never wait long for a worker
+const TICKS_PER_SLICE: usize = 2000;
+/// Jobs stop once the render side holds this many path points
+pub const MAX_PATH_POINTS: usize = 500_000;
+/// Nearly circular orbits pass an apsis at every wobble of the integrator, so apsides are only marked on orbits more eccentric than this
+const MIN_APSIS_ECCENTRICITY: f64 = 1e-3;
+
+
+
+/// Which prediction jobs the pool works on first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PredictionPriority {
+    Background = 0,
+    /// The object the user is looking at
+    Focused = 1,
+}
+
+
+/// Fixed set of worker threads that calculate predicted paths. \
+/// Jobs are run a slice at a time, always picking the highest priority job and taking turns between jobs of equal priority.
+#[derive(Resource)]
+pub struct PredictionPool {
+    shared: Arc<PoolShared>,
+    workers: Vec<JoinHandle<()>>,
+}
+impl PredictionPool {
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(PoolShared::default());
+        let workers = (0..workers.max(1)).map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || shared.run_worker())
+        }).collect();
+        Self { shared, workers }
+    }
+
+    /// Pool without worker threads, where jobs only run when run_next_slice is called. \
+    /// Useful to run predictions deterministically, like in tests.
+    pub fn inline() -> Self {
+        Self { shared: Arc::new(PoolShared::default()), workers: vec![] }
+    }
+
+    pub fn get_worker_count(&self) -> usize { self.workers.len() }
+
+    /// Run one slice of the job a worker would pick next on the calling thread. Returns false if no job was waiting.
+    pub fn run_next_slice(&self) -> bool {
+        let Some(job) = self.shared.queue.lock().unwrap().take_next_job() else { return false };
+        self.shared.run_slice(job);
+        true
+    }
+
+    /// Queue a job that steps the system and sends every new position of the requested dynamic bodies to their senders until each reaches its horizon. \
+    /// Static bodies are placed once per tick for the whole group. Targets have to be static bodies or dynamic bodies of the system.
+    pub(super) fn submit(&self, system: GravitySystemManager, requests: Vec<StreamRequest>) {
+        let streams = requests.into_iter().map(|request| BodyStream {
+            index: request.index,
+            horizon: request.settings.horizon,
+            progress: JobProgress::new(&system, request.index),
+            approach: request.settings.target.and_then(|id| ApproachTracker::new(&system, id)),
+            sender: request.sender,
+            control: request.control,
+            done: false,
+        }).collect();
+        let mut queue = self.shared.queue.lock().unwrap();
+        let sequence = queue.next_sequence();
+        queue.jobs.push(PredictionJob { system, streams, sequence });
+        self.shared.available.notify_one();
+    }
+}
+impl Default for PredictionPool {
+    /// Leave a core for the main thread and don't take over the machine
+    fn default() -> Self {
+        let cores = thread::available_parallelism().map_or(2, |n| n.get());
+        Self::new((cores - 1).clamp(1, 4))
+    }
+}
+impl Drop for PredictionPool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+/// A dynamic body of a submitted system whose path should be sent to a path calculator
+pub(super) struct StreamRequest {
+    /// Index of the body in the submitted system
+    pub index: usize,
+    pub settings: PredictionSettings,
+    pub sender: Sender<PathChunk>,
+    pub control: Arc<JobControl>,
+}
+
+
+/// State of a body's path that is shared between the path calculator and the worker predicting it
+#[derive(Debug)]
+pub(super) struct JobControl {
+    cancelled: AtomicBool,
+    priority: AtomicU8,
+    /// Number of path points the render side is holding
+    stored_points: AtomicUsize,
+}
+impl JobControl {
+    pub fn new(priority: PredictionPriority) -> Self {
+        Self { cancelled: AtomicBool::new(false), priority: AtomicU8::new(priority as u8), stored_points: AtomicUsize::new(0) }
+    }
+    pub fn cancel(&self) { self.cancelled.store(true, Ordering::Relaxed) }
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::Relaxed) }
+    pub fn set_priority(&self, priority: PredictionPriority) { self.priority.store(priority as u8, Ordering::Relaxed) }
+    pub fn get_priority(&self) -> PredictionPriority {
+        if self.priority.load(Ordering::Relaxed) == PredictionPriority::Focused as u8 { PredictionPriority::Focused } else { PredictionPriority::Background }
+    }
+    pub fn set_stored_points(&self, points: usize) { self.stored_points.store(points, Ordering::Relaxed) }
+}
+
+
+/// Consecutive positions of a body that were all relative to the same system
+#[derive(Debug)]
+pub struct PathChunk {
+    pub generator: StaticGenerator,
+    pub points: Vec<(DiscreteGravitySystemTime, DVec2)>,
+    /// Events at the same ticks as the points, relative to the same system
+    pub events: Vec<PathEvent>,
+    /// Closest approach to the target found so far, if it got closer during this chunk
+    pub closest_approach: Option<ClosestApproach>,
+}
+impl PathChunk {
+    fn new(body: DynamicBodyRef, time: DiscreteGravitySystemTime) -> Self {
+        Self { generator: body.get_parent_generator().clone(), points: vec![(time, body.get_previous_relative_position())], events: vec![], closest_approach: None }
+    }
+}
+
+
+/// The moment a body is closest to its target
+#[derive(Clone, Debug)]
+pub struct ClosestApproach {
+    pub time: DiscreteGravitySystemTime,
+    pub distance: f64,
+    /// Velocity of the body minus the velocity of the target
+    pub relative_velocity: DVec2,
+    /// System the positions are relative to, which is the one the body is in at that time
+    pub generator: StaticGenerator,
+    pub body_position: DVec2,
+    pub target_position: DVec2,
+}
+
+
+/// Something that happens to a body along its predicted path
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathEvent {
+    pub time: DiscreteGravitySystemTime,
+    /// Position relative to the system the body is in after the event
+    pub relative_position: DVec2,
+    pub kind: PathEventKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathEventKind {
+    Periapsis,
+    Apoapsis,
+    /// Entering the system centered on the named body
+    SoiEntry(String),
+    /// Leaving the system centered on the named body
+    SoiExit(String),
+    /// Touching the named static body. The prediction stops here.
+    Impact(String),
+}
+impl PathEventKind {
+    pub fn get_label(&self) -> String {
+        match self {
+            Self::Periapsis => "Periapsis".into(),
+            Self::Apoapsis => "Apoapsis".into(),
+            Self::SoiEntry(name) => format!("Enter {name}"),
+            Self::SoiExit(name) => format!("Exit {name}"),
+            Self::Impact(name) => format!("Impact {name}"),
+        }
+    }
+}
+
+
+/// How far a job has gone towards its horizon, and the state needed to notice events along the path
+struct JobProgress {
+    start_time: DiscreteGravitySystemTime,
+    soi_transitions: u64,
+    system_depth: usize,
+    /// Name of the body at the center of the system the body is in
+    system_name: String,
+    /// Angle the body has swept around the system it is in since it entered it
+    swept_angle: f64,
+    last_relative_position: DVec2,
+    /// Sign of the radial velocity, which flips at every apsis
+    last_radial_speed: f64,
+}
+impl JobProgress {
+    fn new(system: &GravitySystemManager, index: usize) -> Self {
+        let body = system.body_store.dynamic_bodies.at(index);
+        Self {
+            start_time: system.get_current_time(),
+            soi_transitions: body.get_soi_transitions(),
+            system_depth: body.get_system_depth(),
+            system_name: system.get_body_system_name(system.body_store.dynamic_ids[index]).unwrap_or_default(),
+            swept_angle: 0.,
+            last_relative_position: body.get_current_relative_position(),
+            last_radial_speed: body.get_current_relative_position().dot(body.get_current_relative_velocity()),
+        }
+    }
+
+    /// Account for the last tick, adding the events that happened in it. \
+    /// Returns whether the path should stop, because the horizon was reached or the body hit something.
+    fn update(&mut self, system: &GravitySystemManager, index: usize, horizon: PredictionHorizon, events: &mut Vec<PathEvent>) -> bool {
+        let id = system.body_store.dynamic_ids[index];
+        let body = system.body_store.dynamic_bodies.at(index);
+        let time = system.get_current_time();
+        let relative_position = body.get_current_relative_position();
+        let radial_speed = relative_position.dot(body.get_current_relative_velocity());
+        let mut add_event = |kind| events.push(PathEvent { time, relative_position, kind });
+
+        let changed_system = body.get_soi_transitions() != self.soi_transitions;
+        if changed_system {
+            let system_name = system.get_body_system_name(id).unwrap_or_default();
+            if body.get_system_depth() <= self.system_depth {
+                add_event(PathEventKind::SoiExit(std::mem::take(&mut self.system_name)));
+            }
+            if body.get_system_depth() >= self.system_depth {
+                add_event(PathEventKind::SoiEntry(system_name.clone()));
+            }
+            self.soi_transitions = body.get_soi_transitions();
+            self.system_depth = body.get_system_depth();
+            self.system_name = system_name;
+            self.swept_angle = 0.;
+        } else {
+            self.swept_angle += self.last_relative_position.angle_between(relative_position);
+            let passed_apsis = (self.last_radial_speed < 0.) != (radial_speed < 0.);
+            if passed_apsis && get_eccentricity(system, id) > MIN_APSIS_ECCENTRICITY {
+                add_event(if radial_speed < 0. { PathEventKind::Apoapsis } else { PathEventKind::Periapsis });
+            }
+        }
+        self.last_relative_position = relative_position;
+        self.last_radial_speed = radial_speed;
+
+        if let Some(name) = system.find_static_impact(id).and_then(|i| system.body_store.get_static_body(i)).map(|b| b.get_name()) {
+            add_event(PathEventKind::Impact(name));
+            return true
+        }
+        match horizon {
+            PredictionHorizon::Orbits(orbits) => self.swept_angle.abs() >= orbits * std::f64::consts::TAU,
+            PredictionHorizon::Duration(duration) => (time - self.start_time) as f64 * system.get_tick_length() >= duration,
+            PredictionHorizon::NextEvent => changed_system,
+        }
+    }
+}
+
+/// Eccentricity of the orbit of a dynamic body around the system it is in
+fn get_eccentricity(system: &GravitySystemManager, id: BodyId) -> f64 {
+    let (Some(body), Some(mu)) = (system.body_store.get_dynamic_body(id), system.get_body_system(id).map(|s| s.mu)) else { return 0. };
+    if mu == 0. { return 0. }
+    let (position, velocity) = (body.get_current_relative_position(), body.get_current_relative_velocity());
+    ((velocity.length_squared() - mu / position.length()) * position - position.dot(velocity) * velocity).length() / mu
+}
+
+
+/// Finds the closest approach of a dynamic body to a target
+struct ApproachTracker {
+    target: ApproachTarget,
+    closest_distance: f64,
+}
+enum ApproachTarget {
+    /// Index in the static bodies
+    Static(usize),
+    /// Index in the dynamic bodies
+    Dynamic(usize),
+}
+impl ApproachTracker {
+    fn new(system: &GravitySystemManager, id: BodyId) -> Option<Self> {
+        let target = match system.body_store.get_dynamic_index(id) {
+            Some(index) => ApproachTarget::Dynamic(index),
+            None => ApproachTarget::Static(system.body_store.get_static_index(id)?),
+        };
+        Some(Self { target, closest_distance: f64::INFINITY })
+    }
+
+    /// Check the distance at the current tick, returning the approach if it is the closest so far
+    fn update(&mut self, system: &GravitySystemManager, index: usize) -> Option<ClosestApproach> {
+        let time = system.get_physical_time();
+        let body = system.body_store.dynamic_bodies.at(index);
+        let (target_position, target_velocity) = match self.target {
+            ApproachTarget::Static(index) => {
+                let target = &system.body_store.static_bodies[index];
+                let (parent_pos, parent_vel) = target.get_parent_generator().get_position_and_velocity(time);
+                let (pos, vel) = target.get_static_position().get_position_and_velocity(time);
+                (parent_pos + pos, parent_vel + vel)
+            }
+            ApproachTarget::Dynamic(index) => {
+                let target = system.body_store.dynamic_bodies.at(index);
+                (target.get_current_absolute_position(), target.get_current_absolute_velocity())
+            }
+        };
+        let distance = body.get_current_absolute_position().distance(target_position);
+        if distance >= self.closest_distance { return None }
+        self.closest_distance = distance;
+
+        let center = body.get_parent_generator().get_position(time);
+        Some(ClosestApproach {
+            time: system.get_current_time(),
+            distance,
+            relative_velocity: body.get_current_absolute_velocity() - target_velocity,
+            generator: body.get_parent_generator().clone(),
+            body_position: body.get_current_relative_position(),
+            target_position: target_position - center,
+        })
+    }
+}
+
+
+/// Path of one body of a prediction job
+struct BodyStream {
+    /// Index of the body in the job's system
+    index: usize,
+    horizon: PredictionHorizon,
+    progress: JobProgress,
+    approach: Option<ApproachTracker>,
+    sender: Sender<PathChunk>,
+    control: Arc<JobControl>,
+    /// Whether the body reached its horizon or its path calculator stopped listening
+    done: bool,
+}
+impl BodyStream {
+    fn is_done(&self) -> bool {
+        self.done || self.control.is_cancelled() || self.control.stored_points.load(Ordering::Relaxed) > MAX_PATH_POINTS
+    }
+
+    /// Add the position and events of the body at the current tick to the chunks, starting a new chunk when the body is in another system than the last one
+    fn record_tick(&mut self, system: &GravitySystemManager, chunks: &mut Vec<PathChunk>) {
+        let time = system.get_current_time();
+        let body = system.body_store.dynamic_bodies.at(self.index);
+        match chunks.last_mut() {
+            Some(chunk) if chunk.generator == *body.get_parent_generator() => chunk.points.push((time, body.get_previous_relative_position())),
+            _ => chunks.push(PathChunk::new(body, time)),
+        }
+        let chunk = chunks.last_mut().unwrap();
+        if let Some(closest_approach) = self.approach.as_mut().and_then(|a| a.update(system, self.index)) {
+            chunk.closest_approach = Some(closest_approach);
+        }
+        self.done = self.progress.update(system, self.index, self.horizon, &mut chunk.events);
+    }
+}
+
+
+/// Steps a copy of the system, sending the paths of some of its bodies
+struct PredictionJob {
+    system: GravitySystemManager,
+    streams: Vec<BodyStream>,
+    /// Order the job was queued in, so jobs of equal priority take turns
+    sequence: u64,
+}
+impl PredictionJob {
+    /// Highest priority of the paths that are still being predicted
+    fn get_priority(&self) -> PredictionPriority {
+        self.streams.iter().filter(|s| !s.is_done()).map(|s| s.control.get_priority()).max().unwrap_or(PredictionPriority::Background)
+    }
+    fn is_finished(&self) -> bool {
+        self.streams.iter().all(|s| s.is_done())
+    }
+
+    /// Step the system for one slice and send the new positions. \
+    /// Returns whether the job should be queued again.
+    fn run_slice(&mut self) -> bool {
+        let mut chunks: Vec<Vec<PathChunk>> = self.streams.iter().map(|_| vec![]).collect();
+        for _ in 0..TICKS_PER_SLICE {
+            if self.is_finished() { break }
+            self.system.step();
+            for (stream, chunks) in self.streams.iter_mut().zip(chunks.iter_mut()).filter(|(s, _)| !s.is_done()) {
+                stream.record_tick(&self.system, chunks);
+            }
+        }
+        for (stream, chunks) in self.streams.iter_mut().zip(chunks) {
+            // Sending fails once the path calculator is dropped
+            if !chunks.into_iter().all(|chunk| stream.sender.send(chunk).is_ok()) {
+                stream.done = true;
+            }
+        }
+        !self.is_finished()
+    }
+}
+
+
+#[derive(Default)]
+struct PoolQueue {
+    jobs: Vec<PredictionJob>,
+    sequence: u64,
+    shutdown: bool,
+}
+impl PoolQueue {
+    fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /// Remove and return the highest priority job that has waited longest
+    fn take_next_job(&mut self) -> Option<PredictionJob> {
+        self.jobs.retain(|job| !job.is_finished());
+        let (index, _) = self.jobs
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, job)| (job.get_priority(), std::cmp::Reverse(job.sequence)))?;
+        Some(self.jobs.swap_remove(index))
+    }
+}
+
+
+#[derive(Default)]
+struct PoolShared {
+    queue: Mutex<PoolQueue>,
+    available: Condvar,
+}
+impl PoolShared {
+    fn run_worker(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if queue.shutdown { return }
+                    if let Some(job) = queue.take_next_job() { break job }
+                    queue = self.available.wait(queue).unwrap();
+                }
+            };
+            self.run_slice(job);
+        }
+    }
+
+    /// Run a slice of the job and queue it again if it isn't finished
+    fn run_slice(&self, mut job: PredictionJob) {
+        if job.run_slice() {
+            let mut queue = self.queue.lock().unwrap();
+            job.sequence = queue.next_sequence();
+            queue.jobs.push(job);
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::TryRecvError;
+    use bevy::color::Color;
+    use crate::{gravity_system_tree::{builder::GravitySystemBuilder, dynamic_body::DynamicBody, static_body::{StaticBody, StaticPosition}}, math::get_orbital_speed, path_calculator::{PathCalculator, PredictionSettings}};
+    use super::*;
+
+    /// Bodies starting at the radii with the provided fractions of circular orbit speed
+    fn star_manager(bodies: &[(f64, f64)]) -> GravitySystemManager {
+        let star_mass = 1e22;
+        let bodies = bodies.iter().map(|(radius, speed)| DynamicBody::new(DVec2::X * *radius, DVec2::Y * get_orbital_speed(star_mass, *radius) * *radius * *speed, 1., 1., Color::WHITE, "".into())).collect::<Vec<_>>();
+        GravitySystemManager::new(GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, star_mass, 1_000., Color::WHITE, "Star".into())])
+            .with_dynamic_bodies(&bodies))
+    }
+
+    fn orbit_manager() -> GravitySystemManager {
+        star_manager(&[(10_000., 1.), (20_000., 1.)])
+    }
+
+    /// Run every job to the end and merge every point into the path
+    fn receive_all(pool: &PredictionPool, calculator: &mut PathCalculator) {
+        run_all(pool);
+        while let Ok(chunk) = calculator.receiver.get().try_recv() {
+            calculator.process_chunk(chunk);
+        }
+    }
+
+    fn run_all(pool: &PredictionPool) {
+        while pool.run_next_slice() {}
+    }
+
+    /// Take every point that has arrived without merging them into the path
+    fn drain_points(calculator: &mut PathCalculator) -> Result<usize, TryRecvError> {
+        let mut points = 0;
+        loop {
+            match calculator.receiver.get().try_recv() {
+                Ok(chunk) => points += chunk.points.len(),
+                Err(TryRecvError::Empty) => return Ok(points),
+                Err(e) => return if points > 0 { Ok(points) } else { Err(e) },
+            }
+        }
+    }
+
+    #[test]
+    fn focused_job_runs_first_and_cancelled_job_stops() {
+        let pool = PredictionPool::inline();
+        let manager = orbit_manager();
+        let ids = &manager.body_store.dynamic_ids;
+        let mut focused = PathCalculator::new(&pool, &manager, ids[1], PredictionPriority::Focused, PredictionSettings::default()).unwrap();
+        let mut background = PathCalculator::new(&pool, &manager, ids[0], PredictionPriority::Background, PredictionSettings::default()).unwrap();
+
+        // The background job has waited longer after the first slice, but the focused one keeps being picked
+        for _ in 0..10 {
+            assert!(pool.run_next_slice());
+        }
+        assert_eq!(drain_points(&mut focused), Ok(10 * TICKS_PER_SLICE));
+        assert_eq!(drain_points(&mut background), Ok(0));
+
+        // Once the focused job is cancelled it is dropped along with its sender, and the background job runs
+        focused.cancel();
+        for _ in 0..3 {
+            assert!(pool.run_next_slice());
+        }
+        assert_eq!(drain_points(&mut focused), Err(TryRecvError::Disconnected));
+        assert_eq!(drain_points(&mut background), Ok(3 * TICKS_PER_SLICE));
+
+        // Dropping a path calculator cancels its job
+        let control = background.control.clone();
+        drop(background);
+        assert!(control.is_cancelled());
+    }
+
+    #[test]
+    fn job_stops_at_duration_horizon() {
+        let pool = PredictionPool::inline();
+        let manager = orbit_manager();
+        let settings = PredictionSettings { horizon: PredictionHorizon::Duration(2.5 * TICKS_PER_SLICE as f64 * manager.get_tick_length()), tolerance: 0., ..PredictionSettings::default() };
+        let mut calculator = PathCalculator::new(&pool, &manager, manager.body_store.dynamic_ids[0], PredictionPriority::Focused, settings).unwrap();
+
+        run_all(&pool);
+        assert_eq!(drain_points(&mut calculator), Ok(TICKS_PER_SLICE * 5 / 2));
+        assert_eq!(drain_points(&mut calculator), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn eccentric_orbit_has_alternating_apsides() {
+        let pool = PredictionPool::inline();
+        let manager = star_manager(&[(20_000., 0.8)]);
+        let settings = PredictionSettings { horizon: PredictionHorizon::Orbits(2.), tolerance: 0., ..PredictionSettings::default() };
+        let mut calculator = PathCalculator::new(&pool, &manager, manager.body_store.dynamic_ids[0], PredictionPriority::Focused, settings).unwrap();
+        receive_all(&pool, &mut calculator);
+
+        // The body starts at apoapsis, which is marked on the first tick
+        let events = calculator.get_events().map(|(_, e)| e).collect::<Vec<_>>();
+        let kinds = events.iter().map(|e| e.kind.clone()).collect::<Vec<_>>();
+        assert!(kinds.starts_with(&[PathEventKind::Apoapsis, PathEventKind::Periapsis, PathEventKind::Apoapsis, PathEventKind::Periapsis]), "{kinds:?}");
+        let (periapsis, apoapsis) = (events[1].relative_position.length(), events[2].relative_position.length());
+        assert!(periapsis < 15_000. && (apoapsis - 20_000.).abs() < 10., "{periapsis} {apoapsis}");
+    }
+
+    #[test]
+    fn falling_body_stops_at_impact() {
+        let pool = PredictionPool::inline();
+        let manager = star_manager(&[(20_000., 0.)]);
+        let settings = PredictionSettings { horizon: PredictionHorizon::Orbits(1.), tolerance: 0., ..PredictionSettings::default() };
+        let mut calculator = PathCalculator::new(&pool, &manager, manager.body_store.dynamic_ids[0], PredictionPriority::Focused, settings).unwrap();
+        receive_all(&pool, &mut calculator);
+
+        let (_, last) = calculator.get_events().last().unwrap();
+        assert_eq!(last.kind, PathEventKind::Impact("Star".into()));
+        assert!(last.relative_position.length() < 1_001.);
+        assert_eq!(calculator.paths.last_time(), Some(last.time));
+    }
+
+    #[test]
+    fn counter_rotating_bodies_approach_after_quarter_orbit() {
+        let pool = PredictionPool::inline();
+        let mut manager = star_manager(&[(10_000., 1.), (10_000., 1.)]);
+        let ids = manager.body_store.dynamic_ids.clone();
+        let speed = manager.body_store.dynamic_bodies.at(1).get_current_relative_velocity().length();
+        manager.set_dynamic_body_state(ids[1], -DVec2::X * 10_000., DVec2::Y * speed);
+        let settings = PredictionSettings { horizon: PredictionHorizon::Orbits(0.5), tolerance: 0., target: Some(ids[1]) };
+        let mut calculator = PathCalculator::new(&pool, &manager, ids[0], PredictionPriority::Focused, settings).unwrap();
+        receive_all(&pool, &mut calculator);
+
+        // Both bodies reach the top of the circle at the same time, moving in opposite directions
+        let approach = calculator.get_closest_approach().unwrap();
+        let quarter_orbit = std::f64::consts::FRAC_PI_2 * 10_000. / speed / manager.get_tick_length();
+        assert!((approach.time as f64 - quarter_orbit).abs() < 10., "{} {quarter_orbit}", approach.time);
+        assert!(approach.distance < 10., "{}", approach.distance);
+        assert!((approach.relative_velocity + DVec2::X * 2. * speed).length() < speed * 0.01, "{}", approach.relative_velocity);
+        assert!((approach.body_position - approach.target_position).length() < 10.);
+    }
+
+    #[test]
+    fn group_paths_match_paths_predicted_alone() {
+        let pool = PredictionPool::inline();
+        let manager = star_manager(&[(10_000., 0.9), (20_000., 1.1)]);
+        let ids = manager.body_store.dynamic_ids.clone();
+        let settings = PredictionSettings { horizon: PredictionHorizon::Duration(1.), tolerance: 0., ..PredictionSettings::default() };
+        let mut group = PathCalculator::new_group(&pool, &manager, &ids, PredictionPriority::Focused, settings);
+        assert_eq!(group.len(), 2);
+        assert_eq!(group[0].get_group(), group[1].get_group());
+
+        let positions = |calculator: &PathCalculator| calculator.get_path_records().iter().map(|r| (r.tick, r.x, r.y)).collect::<Vec<_>>();
+        for (calculator, id) in group.iter_mut().zip(&ids) {
+            receive_all(&pool, calculator);
+            let mut alone = PathCalculator::new(&pool, &manager, *id, PredictionPriority::Focused, settings).unwrap();
+            receive_all(&pool, &mut alone);
+            assert_eq!(positions(calculator), positions(&alone));
+        }
+
+        // Dropping one path calculator of a group doesn't stop the others
+        let settings = PredictionSettings { horizon: PredictionHorizon::Orbits(100.), ..settings };
+        let mut group = PathCalculator::new_group(&pool, &manager, &ids, PredictionPriority::Focused, settings);
+        let mut second = group.pop().unwrap();
+        drop(group);
+        for _ in 0..3 {
+            assert!(pool.run_next_slice());
+        }
+        assert_eq!(drain_points(&mut second), Ok(3 * TICKS_PER_SLICE));
+    }
+
+    #[test]
+    fn edited_body_restarts_path_and_keeps_stale_path() {
+        let pool = PredictionPool::inline();
+        let mut manager = orbit_manager();
+        let id = manager.body_store.dynamic_ids[0];
+        let mut calculator = PathCalculator::new(&pool, &manager, id, PredictionPriority::Focused, PredictionSettings::default()).unwrap();
+        pool.run_next_slice();
+        calculator.receive();
+        assert!(calculator.paths.len() > 1);
+        assert!(!calculator.is_outdated(&manager));
+
+        assert!(manager.set_dynamic_body_state(id, DVec2::X * 15_000., DVec2::ZERO));
+        assert!(calculator.is_outdated(&manager));
+        let old_control = calculator.control.clone();
+        assert!(calculator.restart(&pool, &manager));
+        assert!(old_control.is_cancelled());
+        assert!(!calculator.is_outdated(&manager));
+        assert!(calculator.has_stale_path());
+
+        // The new path starts from the edited position
+        pool.run_next_slice();
+        calculator.receive();
+        let (_, first_position) = calculator.paths.relative_path_segments[0].path[0];
+        assert!((first_position - DVec2::X * 15_000.).length() < 1.);
+    }
+
+    /// Two overlapping systems at the same depth, with a body flying at 1e4 along x from one straight into the other
+    fn sibling_crossing_manager() -> GravitySystemManager {
+        let side = |angle: f64, name: &str| GravitySystemBuilder::new()
+            .with_position(StaticPosition::Circular { radius: 1000., speed: 0., start_angle: angle })
+            .with_radius(1100.)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, 1., 1., Color::WHITE, name.into())]);
+        let left = side(std::f64::consts::PI, "Left").with_dynamic_bodies(&[DynamicBody::new(DVec2::Y * 100., DVec2::X * 1e4, 1., 1., Color::WHITE, "".into())]);
+        GravitySystemManager::new(GravitySystemBuilder::new()
+            .with_position(StaticPosition::Still)
+            .with_radius(1e9)
+            .with_time_step(1)
+            .with_static_bodies(&[StaticBody::new(StaticPosition::Still, 1., 1., Color::WHITE, "Star".into())])
+            .with_children(&[left, side(0., "Right")]))
+    }
+
+    #[test]
+    fn crossing_into_sibling_system_starts_new_chunk() {
+        let manager = sibling_crossing_manager();
+        let start = manager.body_store.dynamic_bodies.at(0).get_current_absolute_position();
+        let pool = PredictionPool::inline();
+        let settings = PredictionSettings { horizon: PredictionHorizon::Duration(1500. * manager.get_tick_length()), tolerance: 0., ..PredictionSettings::default() };
+        let mut calculator = PathCalculator::new(&pool, &manager, manager.body_store.dynamic_ids[0], PredictionPriority::Focused, settings).unwrap();
+        run_all(&pool);
+
+        let chunks = std::iter::from_fn(|| calculator.receiver.get().try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 2);
+        assert_ne!(chunks[0].generator, chunks[1].generator);
+        assert_eq!(chunks[0].generator.len(), chunks[1].generator.len());
+        // Every point is where the body flies in a straight line, once placed relative to the system of its chunk
+        for chunk in &chunks {
+            for (tick, position) in chunk.points.iter().skip(1) {
+                let time = *tick as f64 * manager.get_tick_length();
+                let expected = start + DVec2::X * 1e4 * time;
+                assert!((chunk.generator.get_position(time) + *position - expected).length() < 2., "{tick} {position}");
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_into_sibling_system_starts_new_segment() {
+        let manager = sibling_crossing_manager();
+        let pool = PredictionPool::inline();
+        let settings = PredictionSettings { horizon: PredictionHorizon::Duration(1500. * manager.get_tick_length()), ..PredictionSettings::default() };
+        let mut calculator = PathCalculator::new(&pool, &manager, manager.body_store.dynamic_ids[0], PredictionPriority::Focused, settings).unwrap();
+        receive_all(&pool, &mut calculator);
+
+        let segments = &calculator.paths.relative_path_segments;
+        assert_eq!(segments.len(), 2);
+        assert_ne!(segments[0].generator, segments[1].generator);
+    }
+}
@@ -0,0 +1,71 @@
+use bevy::{math::DVec2, prelude::*};
+
+use crate::gravity_system_tree::{static_generator::StaticGenerator, system_tree::DiscreteGravitySystemTime};
+
+use super::FuturePaths;
+
+
+
+/// Points a trail keeps at most. The oldest points are dropped first.
+const MAX_TRAIL_POINTS: usize = 20_000;
+
+
+
+/// Past positions of a body relative to the system it was in, decimated the same way as predicted paths. \
+/// Points older than the trail length are dropped, and the rest fade out with age when drawn.
+#[derive(Component, Default)]
+pub struct Trail {
+    pub(super) paths: FuturePaths,
+}
+impl Trail {
+    /// Add the position of the body relative to its system at a tick. A new segment starts when the body changes system.
+    pub fn record(&mut self, generator: &StaticGenerator, position: DVec2, time: DiscreteGravitySystemTime, tolerance: f64) {
+        if self.paths.last_time().is_some_and(|t| t >= time) { return }
+        self.paths.insert_new_position(generator, position, time, tolerance);
+        while self.paths.len() > MAX_TRAIL_POINTS {
+            let Some(first) = self.paths.first_time() else { break };
+            self.paths.drop_until_time(first);
+        }
+    }
+
+    /// Forget every point at or before the tick
+    pub fn drop_until_time(&mut self, time: DiscreteGravitySystemTime) {
+        self.paths.drop_until_time(time);
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::gravity_system_tree::static_body::StaticPosition;
+
+    use super::*;
+
+    #[test]
+    fn trail_starts_new_segment_when_system_changes() {
+        let mut moon = StaticGenerator::new();
+        moon.push_end(StaticPosition::Circular { radius: 1000., speed: 0.01, start_angle: 0. });
+        let mut trail = Trail::default();
+        for time in 0..100 {
+            let generator = if time < 50 { StaticGenerator::new() } else { moon.clone() };
+            trail.record(&generator, DVec2::new(time as f64, 0.), time, 1.);
+        }
+        // Straight lines in each system keep only their ends
+        assert_eq!(trail.paths.relative_path_segments.len(), 2);
+        assert_eq!(trail.len(), 4);
+
+        trail.drop_until_time(60);
+        assert_eq!(trail.paths.relative_path_segments.len(), 1);
+        assert_eq!(trail.paths.first_time(), Some(99));
+    }
+}
@@ -1,7 +1,7 @@
 use bevy::{math::DVec2, prelude::*};
-use bevy_egui::{egui::{panel, DragValue, RichText, SidePanel, Slider, Button}, EguiContexts};
+use bevy_egui::{egui::{panel, Button, ComboBox, DragValue, RichText, SidePanel, Slider}, EguiContexts};
 use rand::Rng;
-use crate::{gravity_system_tree::system_manager::{self, GravitySystemManager}, path_calculator::PathCalculator, visual_object::{CircleMesh, DrawOptions, FollowObjectResource, SelectedObjects, SimulationState, VisualObjectBundle, VisualObjectData}};
+use crate::{diagnostics::EnergyDiagnostics, gravity_system_tree::{calendar::{format_duration, parse_duration}, frame::ReferenceFrame, static_body::StaticPosition, system_manager::{self, GravitySystemManager}}, path_calculator::{PathCalculator, PredictionHorizon, PredictionPool, PredictionPriority, PredictionSettings}, visual_object::{BodyEntityMap, CircleMesh, DrawOptions, FollowObjectResource, SelectedObjects, SimulationState, TrailMode, TrajectoryExporter, VisualObjectBundle, VisualObjectData}};
 
 
 
@@ -21,14 +21,27 @@ impl Default for ObjectSpawnOptions {
     }
 }
 
+#[derive(Default)]
+pub struct RunUntilInput {
+    time: String,
+    error: Option<String>,
+}
+
 pub fn side_panel(
     mut contexts: EguiContexts,
     mut sim_state: ResMut<SimulationState>,
     mut draw_options: ResMut<DrawOptions>,
     mut spawn_options: Local<ObjectSpawnOptions>,
+    mut run_until_input: Local<RunUntilInput>,
+    mut conversion_error: Local<Option<String>>,
     selected_objects: Res<SelectedObjects>,
     mut follow_object_resource: ResMut<FollowObjectResource>,
-    system_manager: Res<GravitySystemManager>,
+    mut system_manager: ResMut<GravitySystemManager>,
+    entity_map: Res<BodyEntityMap>,
+    mut exporter: ResMut<TrajectoryExporter>,
+    mut diagnostics: ResMut<EnergyDiagnostics>,
+    mut path_query: Query<&mut PathCalculator>,
+    prediction_pool: Res<PredictionPool>,
     mut commands: Commands,
 ) {
     SidePanel::new(panel::Side::Right, "sidepanel")
@@ -44,20 +57,171 @@ pub fn side_panel(
             ui.vertical(|ui| {
                 ui.horizontal(|ui| {
                     ui.checkbox(&mut sim_state.running, "Run:");
-                    ui.add(bevy_egui::egui::Slider::new(&mut sim_state.run_speed, 0.01..=50_000.0).logarithmic(true))
+                    ui.add(bevy_egui::egui::Slider::new(&mut sim_state.run_speed, 0.01..=50_000.0).logarithmic(true));
+                    ui.label(format!("{}/s", format_duration(sim_state.run_speed * 1000. * system_manager.get_tick_length())));
                 });
-                ui.label(format!("tick: {}", unsafe { sim_state.current_time.to_int_unchecked::<u64>() }))
+                ui.label(system_manager.get_date(sim_state.current_time));
+                ui.label(format!("tick: {}", unsafe { sim_state.current_time.to_int_unchecked::<u64>() }));
+                ui.horizontal(|ui| {
+                    ui.label("Run Until");
+                    ui.text_edit_singleline(&mut run_until_input.time).on_hover_text("A date, a duration since the start such as 3d 4h, or a duration from now such as +1h");
+                    if ui.button("Go").clicked() {
+                        run_until_input.error = match system_manager.parse_tick(&run_until_input.time) {
+                            Ok(tick) if tick > sim_state.current_time => {
+                                sim_state.run_until = Some(tick);
+                                sim_state.running = true;
+                                None
+                            }
+                            Ok(_) => Some("that time has already passed".into()),
+                            Err(e) => Some(format!("invalid time: {e:?}")),
+                        };
+                    }
+                });
+                if let Some(target) = sim_state.run_until {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("running until {}", system_manager.get_date(target)));
+                        if ui.button("Cancel").clicked() {
+                            sim_state.run_until = None;
+                        }
+                    });
+                }
+                if let Some(error) = &run_until_input.error {
+                    ui.label(RichText::new(error).color(bevy_egui::egui::Color32::RED));
+                }
             });
             
 
             ui.collapsing("Draw Options", |ui| {
                 ui.checkbox(&mut draw_options.draw_velocity_arrow, "Show Velocity");
                 ui.checkbox(&mut draw_options.draw_future_path, "Show Path");
+                ui.checkbox(&mut draw_options.draw_path_events, "Show Path Events");
+                ui.checkbox(&mut draw_options.draw_orbit_lines, "Show Orbits");
+                ui.checkbox(&mut draw_options.draw_system_radii, "Show System Radii");
+                ui.horizontal(|ui| {
+                    ui.label("Path Frame");
+                    let mut frames = vec![(ReferenceFrame::Local, "Local".to_string()), (ReferenceFrame::Inertial, "Inertial".to_string())];
+                    frames.extend(system_manager.get_system_names().into_iter().map(|(id, name)| (ReferenceFrame::System(id), format!("Centered on {name}"))));
+                    let store = &system_manager.body_store;
+                    frames.extend(store.static_bodies.iter().zip(&store.static_ids)
+                        // The body at the center of the root system doesn't orbit anything
+                        .filter(|(body, _)| body.get_static_position() != &StaticPosition::Still || body.get_parent_generator().len() > 0)
+                        .map(|(body, id)| (ReferenceFrame::CoRotating(*id), format!("Rotating with {}", body.get_name()))));
+                    let selected = frames.iter().find(|(frame, _)| *frame == draw_options.reference_frame).map_or("Local".to_string(), |(_, name)| name.clone());
+                    ComboBox::from_id_source("path frame")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            for (frame, name) in frames {
+                                ui.selectable_value(&mut draw_options.reference_frame, frame, name);
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Trails");
+                    ComboBox::from_id_source("trail mode")
+                        .selected_text(draw_options.trail_mode.get_name())
+                        .show_ui(ui, |ui| {
+                            for mode in [TrailMode::Off, TrailMode::Selected, TrailMode::All] {
+                                ui.selectable_value(&mut draw_options.trail_mode, mode, mode.get_name());
+                            }
+                        });
+                    let speed = draw_options.trail_length * 0.01;
+                    ui.add(DragValue::new(&mut draw_options.trail_length)
+                        .range(1.0..=f64::MAX)
+                        .speed(speed)
+                        .custom_formatter(|seconds, _| format_duration(seconds))
+                        .custom_parser(|input| parse_duration(input).ok()))
+                        .on_hover_text("How far back trails reach");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Trail Tolerance");
+                    ui.add(DragValue::new(&mut draw_options.trail_tolerance).range(0.0..=10.).speed(0.01).suffix(" px"));
+                }).response.on_hover_text("How far trails may stray from the recorded positions on screen, at the zoom they were recorded at");
                 ui.checkbox(&mut follow_object_resource.follow_object, "Follow Focused Object");
             });
 
             ui.separator();
 
+            ui.collapsing("Predicted Paths", |ui| {
+                let focused_body = selected_objects.focused.as_ref().and_then(|(e, _)| entity_map.get_body(*e));
+                let mut bodies = None;
+                if ui.button("Predict Selected Bodies").clicked() {
+                    bodies = Some(selected_objects.selected.iter().filter_map(|e| entity_map.get_body(*e)).collect::<Vec<_>>());
+                }
+                if ui.add_enabled(focused_body.is_some(), Button::new("Predict Focused Body's System")).clicked() {
+                    bodies = focused_body
+                        .and_then(|id| system_manager.get_body_system(id))
+                        .map(|system| system.dynamic_body_indices.iter().map(|i| system_manager.body_store.dynamic_ids[*i]).collect());
+                }
+                // Every body in the group is stepped by one job, so static bodies are placed once per tick for all of them
+                if let Some(bodies) = bodies {
+                    for path_calc in PathCalculator::new_group(&prediction_pool, &system_manager, &bodies, PredictionPriority::Background, PredictionSettings::default()) {
+                        if let Some(mut ec) = entity_map.get_entity(path_calc.get_body()).and_then(|e| commands.get_entity(e)) {
+                            ec.insert(path_calc);
+                        }
+                    }
+                }
+                if ui.add_enabled(!path_query.is_empty(), Button::new("Remove All Predicted Paths")).clicked() {
+                    for entity in path_query.iter().filter_map(|p| entity_map.get_entity(p.get_body())) {
+                        commands.entity(entity).remove::<PathCalculator>();
+                    }
+                }
+            });
+
+            ui.separator();
+
+            ui.collapsing("Export", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Trajectory File");
+                    ui.text_edit_singleline(&mut exporter.path);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Every");
+                    ui.add(DragValue::new(&mut exporter.every).range(1..=u64::MAX).suffix(" ticks"));
+                });
+                if exporter.is_recording() {
+                    if ui.button("Stop Recording").clicked() {
+                        exporter.stop();
+                    }
+                } else if ui.button("Record Selected Bodies").clicked() {
+                    let bodies = selected_objects.selected
+                        .iter()
+                        .filter_map(|e| entity_map.get_body(*e))
+                        .filter(|id| system_manager.body_store.get_dynamic_index(*id).is_some())
+                        .collect();
+                    exporter.start(bodies, &system_manager);
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Predicted Path File");
+                    ui.text_edit_singleline(&mut exporter.predicted_path);
+                });
+                let focused_path = selected_objects.focused.as_ref().and_then(|(e, _)| path_query.get(*e).ok());
+                if ui.add_enabled(focused_path.is_some(), Button::new("Export Focused Predicted Path")).clicked() {
+                    exporter.export_predicted_path(focused_path.unwrap());
+                }
+                if let Some(error) = &exporter.error {
+                    ui.label(RichText::new(error).color(bevy_egui::egui::Color32::RED));
+                }
+            });
+
+            ui.separator();
+
+            ui.collapsing("Diagnostics", |ui| {
+                if diagnostics.is_empty() {
+                    ui.label("No bodies tracked");
+                }
+                for (id, tracker) in diagnostics.iter() {
+                    let name = system_manager.body_store.get_dynamic_body(id).map_or(String::new(), |b| b.get_name());
+                    ui.label(RichText::new(name).strong());
+                    ui.label(format!("energy: {:.6e}  drift: {:.3e}", tracker.current.energy, tracker.energy.current));
+                    ui.label(format!("    max: {:.3e}  mean: {:.3e}", tracker.energy.max, tracker.energy.mean));
+                    ui.label(format!("angular momentum: {:.6e}  drift: {:.3e}", tracker.current.angular_momentum, tracker.angular_momentum.current));
+                    ui.label(format!("    max: {:.3e}  mean: {:.3e}", tracker.angular_momentum.max, tracker.angular_momentum.mean));
+                    ui.label(format!("{} samples since tick {}", tracker.samples, tracker.reference_time));
+                }
+            });
+
+            ui.separator();
+
             ui.collapsing("Spawn Object", |ui| {
                 ui.horizontal(|ui| {
                     ui.label("Position");
@@ -113,24 +277,25 @@ pub fn side_panel(
                     return;
                 };
                 ui.label(format!("{}", data.name));
+                let mut state_changed = false;
                 ui.horizontal(|ui| {
                     ui.label("Position");
                     let x_pos_changed = ui.add(DragValue::new(&mut data.position.x).prefix("X: ")).changed();
                     let y_pos_changed = ui.add(DragValue::new(&mut data.position.y).prefix("Y: ")).changed();
-                    if x_pos_changed || y_pos_changed {
-                        //let event = ChangeEvent::new(e, Change::SetPosition(data.position));
-                        //change_event_writer.send(event);
-                    }
+                    state_changed |= x_pos_changed || y_pos_changed;
                 });
                 ui.horizontal(|ui| {
                     ui.label("Velocity");
                     let x_vel_changed = ui.add(DragValue::new(&mut data.velocity.x).prefix("X: ")).changed();
                     let y_vel_changed = ui.add(DragValue::new(&mut data.velocity.y).prefix("Y: ")).changed();
-                    if x_vel_changed || y_vel_changed {
-                        //let event = ChangeEvent::new(e, Change::SetVelocity(data.velocity));
-                        //change_event_writer.send(event);
-                    }
+                    state_changed |= x_vel_changed || y_vel_changed;
                 });
+                // Shown velocities are relative to the body's system, so add the system's velocity back
+                let edited_body = entity_map.get_body(e).and_then(|id| Some((id, system_manager.body_store.get_dynamic_body(id)?)));
+                if let Some((id, body)) = edited_body.filter(|_| state_changed) {
+                    let velocity = data.velocity + body.get_current_absolute_velocity() - body.get_current_relative_velocity();
+                    system_manager.set_dynamic_body_state(id, data.position, velocity);
+                }
                 ui.horizontal(|ui| {
                     ui.label("Mass");
                     ui.style_mut().spacing.slider_width = 225.;
@@ -155,12 +320,100 @@ pub fn side_panel(
                     let color_changed = bevy_egui::egui::color_picker::color_edit_button_rgb(ui, &mut rgb).changed();
                 });
 
+                if let Some(id) = entity_map.get_body(e).filter(|id| system_manager.body_store.get_dynamic_index(*id).is_some()) {
+                    let mut is_tracked = diagnostics.is_tracked(id);
+                    if ui.checkbox(&mut is_tracked, "Track Energy Drift").changed() {
+                        if is_tracked { diagnostics.track(id) } else { diagnostics.untrack(id) }
+                    }
+                }
+
                 if ui.button("add path calculator").clicked() {
-                    if let Some(mut ec) = commands.get_entity(e) {
-                        let path_calc = PathCalculator::new(&system_manager, e);
+                    let body = entity_map.get_body(e).filter(|id| system_manager.body_store.get_dynamic_index(*id).is_some());
+                    let path_calc = body.and_then(|id| PathCalculator::new(&prediction_pool, &system_manager, id, PredictionPriority::Focused, PredictionSettings::default()));
+                    if let (Some(mut ec), Some(path_calc)) = (commands.get_entity(e), path_calc) {
                         ec.insert(path_calc);
                     }
                 }
+
+                if let Ok(mut path_calc) = path_query.get_mut(e) {
+                    let mut settings = path_calc.get_settings();
+                    ui.horizontal(|ui| {
+                        ui.label("Predict");
+                        ComboBox::from_id_source("prediction horizon")
+                            .selected_text(settings.horizon.get_name())
+                            .show_ui(ui, |ui| {
+                                for horizon in [PredictionHorizon::Orbits(5.), PredictionHorizon::Duration(86_400.), PredictionHorizon::NextEvent] {
+                                    let is_selected = horizon.get_name() == settings.horizon.get_name();
+                                    if ui.selectable_label(is_selected, horizon.get_name()).clicked() && !is_selected {
+                                        settings.horizon = horizon;
+                                    }
+                                }
+                            });
+                        match &mut settings.horizon {
+                            PredictionHorizon::Orbits(orbits) => { ui.add(DragValue::new(orbits).range(0.1..=1000.).speed(0.1)); }
+                            PredictionHorizon::Duration(duration) => {
+                                let speed = *duration * 0.01;
+                                ui.add(DragValue::new(duration)
+                                    .range(0.0..=f64::MAX)
+                                    .speed(speed)
+                                    .custom_formatter(|seconds, _| format_duration(seconds))
+                                    .custom_parser(|input| parse_duration(input).ok()));
+                            }
+                            PredictionHorizon::NextEvent => {}
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Target");
+                        let body_name = |id| {
+                            let store = &system_manager.body_store;
+                            store.get_dynamic_body(id).map(|b| b.get_name()).or_else(|| store.get_static_body(id).map(|b| b.get_name()))
+                        };
+                        ComboBox::from_id_source("prediction target")
+                            .selected_text(settings.target.and_then(body_name).unwrap_or("None".into()))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut settings.target, None, "None");
+                                let store = &system_manager.body_store;
+                                for id in store.static_ids.iter().chain(&store.dynamic_ids).filter(|id| **id != path_calc.get_body()) {
+                                    ui.selectable_value(&mut settings.target, Some(*id), body_name(*id).unwrap_or_default());
+                                }
+                            });
+                    });
+                    if let Some(approach) = path_calc.get_closest_approach() {
+                        let time_left = (approach.time as f64 - sim_state.current_time) * system_manager.get_tick_length();
+                        ui.label(format!("Closest approach {:.1} in {}", approach.distance, format_duration(time_left)));
+                        ui.label(format!("Relative velocity {:.1} ({:.1}, {:.1})", approach.relative_velocity.length(), approach.relative_velocity.x, approach.relative_velocity.y));
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Path Tolerance");
+                        let speed = settings.tolerance * 0.01 + 0.01;
+                        ui.add(DragValue::new(&mut settings.tolerance).range(0.0..=f64::MAX).speed(speed));
+                    }).response.on_hover_text("How far the drawn path may stray from the predicted positions");
+                    if settings != path_calc.get_settings() && !path_calc.set_settings(&prediction_pool, &system_manager, settings) {
+                        commands.entity(e).remove::<PathCalculator>();
+                    }
+                }
+
+                if let Some(id) = entity_map.get_body(e) {
+                    if system_manager.body_store.get_static_index(id).is_some() {
+                        if ui.button("Release From Rails").clicked() {
+                            *conversion_error = system_manager.promote_static_body(id).err().map(|e| format!("can't release body: {e:?}"));
+                        }
+                    } else if ui.button("Put On Rails").clicked() {
+                        *conversion_error = match system_manager.demote_dynamic_body(id) {
+                            Ok(()) => {
+                                diagnostics.untrack(id);
+                                if let Some(mut ec) = commands.get_entity(e) {
+                                    ec.remove::<PathCalculator>();
+                                }
+                                None
+                            }
+                            Err(e) => Some(format!("can't put body on rails: {e:?}")),
+                        };
+                    }
+                    if let Some(error) = &*conversion_error {
+                        ui.label(RichText::new(error).color(bevy_egui::egui::Color32::RED));
+                    }
+                }
             });
         });
 }
@@ -3,8 +3,6 @@ use bevy::{color::Color, math::DVec2};
 use bincode::deserialize;
 use rand::{rngs::ThreadRng, Rng};
 
-use crate::{visual_object::VisualObjectData};
-
 pub fn load_from_file() -> Result<(), ()> {
     let Ok(data) = fs::read("./save.dat") else { return Err(()) };
 
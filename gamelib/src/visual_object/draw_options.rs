@@ -1,16 +1,57 @@
+use crate::gravity_system_tree::{frame::ReferenceFrame, system_tree::PhysicalTime};
+
 use super::*;
 
 
 #[derive(Resource)]
 pub struct DrawOptions {
     pub draw_velocity_arrow: bool,
-    pub draw_future_path: bool
+    pub draw_future_path: bool,
+    /// Markers for apsides, system changes and impacts along predicted paths
+    pub draw_path_events: bool,
+    /// Rails of static bodies, fading out as their systems get too small to show
+    pub draw_orbit_lines: bool,
+    /// Dashed circles at the radius of every system, and markers where predicted paths cross them
+    pub draw_system_radii: bool,
+    /// Frame that predicted paths are drawn in
+    pub reference_frame: ReferenceFrame,
+    /// Which bodies record and draw their past positions
+    pub trail_mode: TrailMode,
+    /// Physical seconds of history that trails keep
+    pub trail_length: PhysicalTime,
+    /// Screen pixels that trails may stray from recorded positions, at the zoom they were recorded at
+    pub trail_tolerance: f64,
 }
 impl Default for DrawOptions {
     fn default() -> Self {
         Self {
             draw_velocity_arrow: true,
-            draw_future_path: true
+            draw_future_path: true,
+            draw_path_events: true,
+            draw_orbit_lines: true,
+            draw_system_radii: false,
+            reference_frame: ReferenceFrame::default(),
+            trail_mode: TrailMode::Off,
+            trail_length: 10. * 86_400.,
+            trail_tolerance: 0.5,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailMode {
+    Off,
+    /// Only selected and focused bodies
+    Selected,
+    All,
+}
+impl TrailMode {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Selected => "Selected",
+            Self::All => "All",
         }
     }
 }
\ No newline at end of file
@@ -25,8 +25,14 @@ mod spawn;
 pub use spawn::*;
 mod follow_object;
 pub use follow_object::*;
+mod trajectory_export;
+pub use trajectory_export::*;
+mod orbit_lines;
+pub use orbit_lines::*;
+mod system_overlay;
+pub use system_overlay::*;
 
-use crate::{gravity_system_tree::{dynamic_body::DynamicBody, static_body::StaticBody}, path_calculator::draw_path, G};
+use crate::{diagnostics::EnergyDiagnostics, gravity_system_tree::{dynamic_body::DynamicBodyRef, static_body::StaticBody}, path_calculator::{draw_path, draw_trails, prioritize_focused_path, receive_predicted_paths, record_trails, restart_outdated_paths, PredictionPool}, G};
 
 pub const CIRCLE_VERTICES: usize = 100;
 
@@ -41,7 +47,7 @@ pub struct VisualObjectData {
     pub name: String,
 }
 impl VisualObjectData {
-    pub fn from_dynamic_body(dynamic_body: &DynamicBody) -> Self {
+    pub fn from_dynamic_body(dynamic_body: DynamicBodyRef) -> Self {
         Self {
             position: dynamic_body.get_interpolated_absolute_position(0.),
             velocity: dynamic_body.get_interpolated_relative_velocity(0.),
@@ -78,10 +84,18 @@ impl Plugin for VisualObjectPlugin {
             .insert_resource(DrawOptions::default())
             .insert_resource(SelectedObjects::default())
             .insert_resource(FollowObjectResource::default())
+            .insert_resource(TrajectoryExporter::default())
+            .insert_resource(EnergyDiagnostics::default())
+            .insert_resource(PredictionPool::default())
             .add_systems(Startup, (init, spawn_background_rect, set_future_path_gizmo_config))
-            .add_systems(PreUpdate, (update_object_data, update_object_positions.after(update_object_data)))
+            .add_systems(PreUpdate, (update_object_data, apply_collision_events.after(update_object_data), update_object_positions.after(apply_collision_events)))
             .add_systems(Update, (
-                draw_path,
+                restart_outdated_paths,
+                receive_predicted_paths.after(restart_outdated_paths),
+                draw_path.after(receive_predicted_paths),
+                prioritize_focused_path,
+                record_trails,
+                draw_trails.after(record_trails),
                 add_material_mesh,
                 move_pseudo_camera,
                 draw_selection_rect,
@@ -90,6 +104,8 @@ impl Plugin for VisualObjectPlugin {
                 update_focused_object_data,
                 draw_velocity_arrows,
                 draw_mini_object_point,
+                draw_orbit_lines,
+                draw_system_radii,
             ));
     }
 }
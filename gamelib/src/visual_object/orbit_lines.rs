@@ -0,0 +1,46 @@
+use crate::{gravity_system_tree::{system_manager::GravitySystemManager, system_tree::get_zoom_fade}, pseudo_camera::camera::CameraState};
+
+use super::*;
+
+
+
+/// Orbits fade in from the size where systems start being drawn and are fully opaque at this many times that size
+const ORBIT_FADE_RANGE: f64 = 10.;
+/// Opacity of an orbit line that is fully faded in
+const ORBIT_LINE_ALPHA: f32 = 0.4;
+/// Number of line segments in an orbit circle
+const ORBIT_RESOLUTION: usize = 256;
+
+
+
+/// Draw the rail of every visible static body around the center it orbits. \
+/// Bodies at the center of a system draw the orbit of the system. Lines fade out with zoom as their orbits get too small to show.
+pub fn draw_orbit_lines(
+    camera_query: Query<&CameraState>,
+    visibility_query: Query<&Visibility>,
+    mut gizmos: Gizmos<FuturePathLineConfig>,
+    system_manager: Res<GravitySystemManager>,
+    entity_map: Res<BodyEntityMap>,
+    draw_options: Res<DrawOptions>,
+    sim_state: Res<SimulationState>,
+) {
+    if !draw_options.draw_orbit_lines { return }
+    let Ok(camera) = camera_query.get_single() else { return };
+    let time = sim_state.current_time * system_manager.get_tick_length();
+    let store = &system_manager.body_store;
+
+    for (body, id) in store.static_bodies.iter().zip(&store.static_ids) {
+        let is_visible = entity_map.get_entity(*id)
+            .and_then(|e| visibility_query.get(e).ok())
+            .is_some_and(|v| *v != Visibility::Hidden);
+        if !is_visible { continue }
+
+        let (center, radius) = body.get_orbit_parameters(time);
+        let screen_radius = camera.get_scale() as f64 * radius;
+        let fade = get_zoom_fade(screen_radius, ORBIT_FADE_RANGE) as f32;
+        if fade == 0. { continue }
+
+        gizmos.circle_2d(camera.physics_to_world_pos(&center), screen_radius as f32, body.get_color().with_alpha(fade * ORBIT_LINE_ALPHA))
+            .resolution(ORBIT_RESOLUTION);
+    }
+}
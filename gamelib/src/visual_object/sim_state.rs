@@ -8,6 +8,8 @@ pub struct SimulationState {
     pub running: bool,
     pub current_time: f64,
     pub run_speed: f64,
+    /// The simulation stops once it reaches this time
+    pub run_until: Option<f64>,
 }
 impl Default for SimulationState {
     fn default() -> Self {
@@ -15,6 +17,7 @@ impl Default for SimulationState {
             running: false,
             current_time: 0.,
             run_speed: 1.,
+            run_until: None,
         }
     }
 }
\ No newline at end of file
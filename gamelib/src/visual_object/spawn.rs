@@ -1,4 +1,80 @@
+use bevy::utils::HashMap;
+use crate::{diagnostics::EnergyDiagnostics, gravity_system_tree::{body_id::BodyId, system_manager::GravitySystemManager, system_tree::BodyStore}};
 use super::*;
 
 
-// Maybe throw event to spawn objects so the ui system doesn't get too bloated
\ No newline at end of file
+// Maybe throw event to spawn objects so the ui system doesn't get too bloated
+
+
+/// Maps simulation bodies to the entities of their visual objects. \
+/// The physics side only knows about BodyIds, this is the only place they are associated with bevy entities.
+#[derive(Resource, Default, Clone)]
+pub struct BodyEntityMap {
+    body_to_entity: HashMap<BodyId, Entity>,
+    entity_to_body: HashMap<Entity, BodyId>,
+}
+impl BodyEntityMap {
+    pub fn insert(&mut self, id: BodyId, entity: Entity) {
+        if let Some(old_entity) = self.body_to_entity.insert(id, entity) {
+            self.entity_to_body.remove(&old_entity);
+        }
+        self.entity_to_body.insert(entity, id);
+    }
+    pub fn remove_body(&mut self, id: BodyId) -> Option<Entity> {
+        let entity = self.body_to_entity.remove(&id)?;
+        self.entity_to_body.remove(&entity);
+        Some(entity)
+    }
+    pub fn get_entity(&self, id: BodyId) -> Option<Entity> {
+        self.body_to_entity.get(&id).copied()
+    }
+    pub fn get_body(&self, entity: Entity) -> Option<BodyId> {
+        self.entity_to_body.get(&entity).copied()
+    }
+}
+
+
+/// Spawns visual objects for the dynamic and static bodies currently in the body store. \
+/// Returns the map used to associate the bodies with the visual objects that were just spawned.
+pub fn spawn_visual_objects(body_store: &BodyStore, commands: &mut Commands) -> BodyEntityMap {
+    let mut entity_map = BodyEntityMap::default();
+    for (db, id) in body_store.dynamic_bodies.iter().zip(&body_store.dynamic_ids) {
+        let bundle = VisualObjectBundle::new(VisualObjectData::from_dynamic_body(db));
+        entity_map.insert(*id, commands.spawn(bundle).id());
+    }
+    for (sb, id) in body_store.static_bodies.iter().zip(&body_store.static_ids) {
+        let bundle = VisualObjectBundle::new(VisualObjectData::from_static_body(sb));
+        entity_map.insert(*id, commands.spawn(bundle).id());
+    }
+    entity_map
+}
+
+
+/// Keep visual objects in sync with collisions resolved by the simulation. \
+/// Bodies that were removed are despawned and deselected, and bodies that were created get new visual objects.
+pub fn apply_collision_events(
+    mut manager: ResMut<GravitySystemManager>,
+    mut entity_map: ResMut<BodyEntityMap>,
+    mut selected_objects: ResMut<SelectedObjects>,
+    mut diagnostics: ResMut<EnergyDiagnostics>,
+    mut commands: Commands,
+) {
+    if manager.get_collision_settings().is_none() { return }
+    for event in manager.take_collision_events() {
+        for id in event.removed {
+            diagnostics.untrack(id);
+            let Some(entity) = entity_map.remove_body(id) else { continue };
+            selected_objects.selected.retain(|e| *e != entity);
+            if selected_objects.focused.as_ref().is_some_and(|(e, _)| *e == entity) {
+                selected_objects.focused = None;
+            }
+            commands.entity(entity).despawn_recursive();
+        }
+        for id in event.created {
+            // The body may already be gone if it collided again before this ran
+            let Some(body) = manager.body_store.get_dynamic_body(id) else { continue };
+            let bundle = VisualObjectBundle::new(VisualObjectData::from_dynamic_body(body));
+            entity_map.insert(id, commands.spawn(bundle).id());
+        }
+    }
+}
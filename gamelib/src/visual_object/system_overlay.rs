@@ -0,0 +1,79 @@
+use std::f32::consts::TAU;
+
+use crate::{gravity_system_tree::{system_manager::GravitySystemManager, system_tree::MIN_VISIBLE_SYSTEM_RADIUS}, path_calculator::{PathCalculator, PathEventKind, PathProjection}, pseudo_camera::camera::CameraState};
+
+use super::*;
+
+
+
+/// Colors of system radii, picked by the depth of the system in the tree
+const DEPTH_COLORS: [Color; 4] = [
+    Color::srgba(0.9, 0.9, 0.9, 0.5),
+    Color::srgba(0.4, 0.7, 1., 0.5),
+    Color::srgba(0.5, 1., 0.5, 0.5),
+    Color::srgba(1., 0.6, 0.3, 0.5),
+];
+/// Number of dashes in a system radius circle
+const DASHES: usize = 48;
+/// Fraction of each dash and gap that is drawn
+const DASH_FILL: f32 = 0.6;
+/// Line segments in each dash
+const DASH_RESOLUTION: usize = 4;
+/// Half the size of the cross drawn where a predicted path crosses a system radius, in screen pixels
+const CROSSING_MARKER_SIZE: f32 = 4.;
+
+
+
+/// Draw the radius of every system large enough to see as a dashed circle colored by depth. \
+/// The system holding the focused body is drawn solid and fully opaque. \
+/// Points where predicted paths enter or leave a system are marked with a cross.
+pub fn draw_system_radii(
+    camera_query: Query<&CameraState>,
+    path_query: Query<&PathCalculator>,
+    mut gizmos: Gizmos<FuturePathLineConfig>,
+    system_manager: Res<GravitySystemManager>,
+    entity_map: Res<BodyEntityMap>,
+    selected_objects: Res<SelectedObjects>,
+    draw_options: Res<DrawOptions>,
+    sim_state: Res<SimulationState>,
+) {
+    if !draw_options.draw_system_radii { return }
+    let Ok(camera) = camera_query.get_single() else { return };
+    let focused_system = selected_objects.focused.as_ref()
+        .and_then(|(e, _)| entity_map.get_body(*e))
+        .and_then(|id| system_manager.get_body_system_id(id));
+
+    for (id, depth, center, radius) in system_manager.get_system_bounds(sim_state.current_time * system_manager.get_tick_length()) {
+        let screen_radius = camera.get_scale() as f64 * radius;
+        if !screen_radius.is_finite() || screen_radius < MIN_VISIBLE_SYSTEM_RADIUS { continue }
+        let (center, screen_radius) = (camera.physics_to_world_pos(&center), screen_radius as f32);
+        let color = DEPTH_COLORS[depth % DEPTH_COLORS.len()];
+
+        if Some(id) == focused_system {
+            gizmos.circle_2d(center, screen_radius, color.with_alpha(1.)).resolution(DASHES * DASH_RESOLUTION);
+            continue
+        }
+        for dash in 0..DASHES {
+            let start = dash as f32 / DASHES as f32 * TAU;
+            let points = (0..=DASH_RESOLUTION).map(|i| {
+                let angle = start + i as f32 / DASH_RESOLUTION as f32 * DASH_FILL * TAU / DASHES as f32;
+                center + Vec2::from_angle(angle) * screen_radius
+            });
+            gizmos.linestrip_2d(points, color);
+        }
+    }
+
+    if !draw_options.draw_future_path { return }
+    let frame = system_manager.get_frame_transform(draw_options.reference_frame);
+    for path_calc in path_query.iter() {
+        let projection = PathProjection::new(frame.clone(), sim_state.current_time, path_calc.get_tick_length());
+        let crossings = path_calc.get_events()
+            .filter(|(_, e)| matches!(e.kind, PathEventKind::SoiEntry(_) | PathEventKind::SoiExit(_)));
+        for (generator, event) in crossings {
+            let position = camera.physics_to_world_pos(&projection.place(generator, event.relative_position, event.time));
+            let color = if matches!(event.kind, PathEventKind::SoiEntry(_)) { Color::srgb(0.5, 1., 0.5) } else { Color::srgb(1., 0.6, 0.3) };
+            gizmos.line_2d(position - Vec2::splat(CROSSING_MARKER_SIZE), position + Vec2::splat(CROSSING_MARKER_SIZE), color);
+            gizmos.line_2d(position + Vec2::new(-CROSSING_MARKER_SIZE, CROSSING_MARKER_SIZE), position + Vec2::new(CROSSING_MARKER_SIZE, -CROSSING_MARKER_SIZE), color);
+        }
+    }
+}
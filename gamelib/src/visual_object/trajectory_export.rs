@@ -0,0 +1,85 @@
+use std::{fs::File, io::BufWriter};
+
+use crate::{export::{BodyStateRecord, ExportFormat, RecordWriter}, gravity_system_tree::{body_id::BodyId, system_manager::GravitySystemManager}, path_calculator::PathCalculator};
+
+use super::*;
+
+
+/// Records the state of bodies to a trajectory file while the simulation runs. \
+/// The file format is picked from the file extension.
+#[derive(Resource)]
+pub struct TrajectoryExporter {
+    /// File that body states are recorded to
+    pub path: String,
+    /// File that predicted paths are written to
+    pub predicted_path: String,
+    /// Record every n ticks
+    pub every: u64,
+    /// Error from the last write, shown in the ui
+    pub error: Option<String>,
+    recording: Option<(RecordWriter<BufWriter<File>>, Vec<BodyId>)>,
+}
+impl Default for TrajectoryExporter {
+    fn default() -> Self {
+        Self {
+            path: "trajectory.csv".into(),
+            predicted_path: "predicted_path.csv".into(),
+            every: 100,
+            error: None,
+            recording: None,
+        }
+    }
+}
+impl TrajectoryExporter {
+    /// Start recording the provided dynamic bodies, beginning with their current state
+    pub fn start(&mut self, bodies: Vec<BodyId>, manager: &GravitySystemManager) {
+        self.stop();
+        match RecordWriter::create(&self.path, ExportFormat::from_path(&self.path)) {
+            Ok(writer) => {
+                self.error = None;
+                self.recording = Some((writer, bodies));
+                self.write_records(manager);
+            },
+            Err(e) => self.error = Some(format!("failed to create {}: {e}", self.path)),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        let Some((mut writer, _)) = self.recording.take() else { return };
+        if let Err(e) = writer.flush() {
+            self.error = Some(format!("failed to write {}: {e}", self.path));
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Record the bodies if the current tick falls on the recording cadence
+    pub fn record(&mut self, manager: &GravitySystemManager) {
+        if self.recording.is_none() || manager.get_current_time() % self.every.max(1) != 0 { return }
+        self.write_records(manager);
+    }
+
+    fn write_records(&mut self, manager: &GravitySystemManager) {
+        let Some((writer, bodies)) = &mut self.recording else { return };
+        let result = bodies
+            .iter()
+            .filter_map(|id| BodyStateRecord::from_manager(manager, *id))
+            .try_for_each(|record| writer.write(&record));
+        if let Err(e) = result {
+            self.error = Some(format!("failed to write {}: {e}", self.path));
+            self.recording = None;
+        }
+    }
+
+    /// Write every point predicted so far by the path calculator
+    pub fn export_predicted_path(&mut self, path_calculator: &PathCalculator) {
+        let result = RecordWriter::create(&self.predicted_path, ExportFormat::from_path(&self.predicted_path))
+            .and_then(|mut writer| {
+                path_calculator.get_path_records().iter().try_for_each(|record| writer.write(record))?;
+                writer.flush()
+            });
+        self.error = result.err().map(|e| format!("failed to write {}: {e}", self.predicted_path));
+    }
+}
@@ -7,14 +7,28 @@ pub fn update_object_data(
     mut sim_state: ResMut<SimulationState>,
     delta_time: Res<Time>,
     mut gravity_system_manager: ResMut<GravitySystemManager>,
+    entity_map: Res<BodyEntityMap>,
+    mut exporter: ResMut<TrajectoryExporter>,
+    mut diagnostics: ResMut<EnergyDiagnostics>,
 ) {
     if sim_state.running {
         sim_state.current_time += delta_time.delta().as_millis() as f64 * sim_state.run_speed;
+        if let Some(target) = sim_state.run_until.filter(|t| sim_state.current_time >= *t) {
+            sim_state.current_time = target;
+            sim_state.running = false;
+            sim_state.run_until = None;
+        }
     }
 
     let Ok(camera) = camera_query.get_single() else { return };
 
-    gravity_system_manager.update_visual_objects(sim_state.current_time as f64, &mut object_query, camera);
+    if exporter.is_recording() || !diagnostics.is_empty() {
+        gravity_system_manager.step_to_time(sim_state.current_time, |manager| {
+            exporter.record(manager);
+            diagnostics.sample(manager);
+        });
+    }
+    gravity_system_manager.update_visual_objects(sim_state.current_time as f64, &entity_map, &mut object_query, camera);
 }
 
 